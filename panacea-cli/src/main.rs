@@ -0,0 +1,356 @@
+#![deny(clippy::unwrap_used, unsafe_code)]
+
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{Parser, Subcommand};
+use sqlx::{
+    database::HasArguments, Decode, Encode, Executor, FromRow, IntoArguments, MySqlPool, PgPool,
+    Pool, SqlitePool, Type,
+};
+use uuid::Uuid;
+
+use panacea::outbox::{
+    Columns, DeadLetter, Dialect, Encryption, EventRow, Key, Outbox, Retention, RetentionAction,
+    StaticKeys,
+};
+use panacea_types::Event;
+
+/// Inspects and operates panacea outbox.
+#[derive(Debug, Parser)]
+#[command(name = "panacea", version)]
+struct Cli {
+    /// Database URL, e.g. `postgres://localhost/app`, `mysql://localhost/app` or
+    /// `sqlite:app.db`.
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+    /// Outbox table name.
+    #[arg(long, default_value = "panacea_outbox")]
+    table: String,
+    /// Database schema of the outbox table.
+    #[arg(long)]
+    schema: Option<String>,
+    /// Outbox table has the layout of the Debezium Outbox Event Router, e.g. `aggregatetype`
+    /// column instead of `topic`.
+    #[arg(long)]
+    debezium: bool,
+    /// Column, that carries given header of events, e.g. `traceparent=trace_context`. May be
+    /// repeated.
+    #[arg(long = "header-column", value_name = "HEADER=COLUMN", value_parser = parse_header_column)]
+    header_columns: Vec<(String, String)>,
+    /// Key, event payloads are encrypted with, as its id and base64 encoded 32 bytes, e.g.
+    /// `2024-01=AAEC...`. May be repeated, e.g. with keys retired after rotation.
+    #[arg(long = "key", value_name = "ID=BASE64", value_parser = parse_key)]
+    keys: Vec<(String, Key)>,
+    /// Id of the key, that events are encrypted with now. Defaults to the first `--key`.
+    #[arg(long, requires = "keys")]
+    current_key: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Counts outbox rows in every state.
+    Count,
+    /// Prints the latest events, optionally following new ones.
+    Tail {
+        /// Number of events to print.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: u32,
+        /// Keep printing new events as they are stored.
+        #[arg(short, long)]
+        follow: bool,
+        /// How often to poll for new events, when following.
+        #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+        interval: Duration,
+    },
+    /// Prints event with given id.
+    Show {
+        /// Event id.
+        id: Uuid,
+    },
+    /// Purges published, expired and dead-lettered rows, that are older than given age.
+    Purge {
+        /// Age of rows to purge, e.g. `7d` or `12h`.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        older_than: Duration,
+        /// Move rows to the archive table instead of deleting them.
+        #[arg(long)]
+        archive: bool,
+        /// Also delete dead letters, that are older than given age. Dead letters are kept, if
+        /// not set.
+        #[arg(long, value_parser = humantime::parse_duration)]
+        dead_letters_older_than: Option<Duration>,
+    },
+    /// Publishes events of given topic again, if they are still in the outbox table.
+    Replay {
+        /// Topic of events to replay.
+        #[arg(long)]
+        topic: String,
+    },
+    /// Inspects and requeues events, that have failed to be published or handled.
+    #[command(subcommand)]
+    DeadLetter(DeadLetterCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum DeadLetterCommand {
+    /// Prints the oldest dead letters.
+    List {
+        /// Number of dead letters to print.
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: u32,
+    },
+    /// Moves event of the dead letter back to the outbox, so it's published again.
+    Requeue {
+        /// Sequence of the dead letter, as printed by `list`.
+        sequence: i64,
+    },
+    /// Deletes the dead letter.
+    Discard {
+        /// Sequence of the dead letter, as printed by `list`.
+        sequence: i64,
+    },
+}
+
+#[async_std::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let mut outbox = Outbox::new().with_table(&cli.table);
+    if let Some(schema) = &cli.schema {
+        outbox = outbox.with_schema(schema);
+    }
+    if cli.debezium {
+        outbox = outbox.with_columns(Columns::debezium());
+    }
+    for (header, column) in cli.header_columns {
+        outbox = outbox.with_header_column(header, column);
+    }
+    if let Some(keys) = static_keys(cli.keys, cli.current_key)? {
+        outbox = outbox.with_encryption(Encryption::new(keys));
+    }
+
+    let url = cli.database_url.as_str();
+    if url.starts_with("postgres:") || url.starts_with("postgresql:") {
+        run(PgPool::connect(url).await?, &outbox, cli.command).await
+    } else if url.starts_with("mysql:") || url.starts_with("mariadb:") {
+        run(MySqlPool::connect(url).await?, &outbox, cli.command).await
+    } else if url.starts_with("sqlite:") {
+        run(SqlitePool::connect(url).await?, &outbox, cli.command).await
+    } else {
+        bail!("unsupported database URL, expected PostgreSQL, MySQL or SQLite one")
+    }
+}
+
+async fn run<DB>(db: Pool<DB>, outbox: &Outbox, command: Command) -> anyhow::Result<()>
+where
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+    for<'c> &'c Pool<DB>: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'r> DeadLetter: FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'r> (i64, i64, i64, i64): FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'r> (i64, Option<i64>): FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'q> i64: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    usize: sqlx::ColumnIndex<<DB as sqlx::Database>::Row>,
+{
+    match command {
+        Command::Count => {
+            let counts = outbox.counts(&db).await?;
+
+            println!("pending:       {}", counts.pending);
+            println!("published:     {}", counts.published);
+            println!("expired:       {}", counts.expired);
+            println!("dead-lettered: {}", counts.dead_lettered);
+        }
+        Command::Tail {
+            lines,
+            follow,
+            interval,
+        } => {
+            let mut last_sequence = 0;
+            for row in outbox.latest_rows(&db, lines).await? {
+                last_sequence = row.sequence;
+                print_row(outbox, row);
+            }
+
+            if follow {
+                loop {
+                    async_std::task::sleep(interval).await;
+
+                    for row in outbox.rows_after(&db, last_sequence, 100).await? {
+                        last_sequence = row.sequence;
+                        print_row(outbox, row);
+                    }
+                }
+            }
+        }
+        Command::Show { id } => {
+            let row = outbox
+                .find_row(&db, id)
+                .await?
+                .with_context(|| format!("there is no event {id} in the outbox table"))?;
+
+            print_row(outbox, row);
+        }
+        Command::Purge {
+            older_than,
+            archive,
+            dead_letters_older_than,
+        } => {
+            let action = if archive {
+                RetentionAction::Archive
+            } else {
+                RetentionAction::Delete
+            };
+            let mut retention = Retention::new(db)
+                .with_outbox(outbox.clone())
+                .with_retention_period(older_than)
+                .with_action(action);
+            if let Some(older_than) = dead_letters_older_than {
+                retention = retention.with_dead_letter_retention_period(older_than);
+            }
+            let purged = retention.purge().await?;
+
+            println!("Purged {purged} rows");
+        }
+        Command::Replay { topic } => {
+            let replayed = outbox.replay_topic(&db, &topic).await?;
+
+            println!("Replayed {replayed} events of `{topic}`");
+        }
+        Command::DeadLetter(DeadLetterCommand::List { lines }) => {
+            for dead_letter in outbox.dead_letters(&db, lines).await? {
+                print_dead_letter(outbox, &dead_letter);
+            }
+        }
+        Command::DeadLetter(DeadLetterCommand::Requeue { sequence }) => {
+            if !outbox.requeue_dead_letter(&db, sequence).await? {
+                bail!("there is no dead letter #{sequence}");
+            }
+
+            println!("Requeued dead letter #{sequence}");
+        }
+        Command::DeadLetter(DeadLetterCommand::Discard { sequence }) => {
+            if !outbox.discard_dead_letter(&db, sequence).await? {
+                bail!("there is no dead letter #{sequence}");
+            }
+
+            println!("Discarded dead letter #{sequence}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses `HEADER=COLUMN` pair of the `--header-column` option.
+fn parse_header_column(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((header, column)) if !header.is_empty() && !column.is_empty() => {
+            Ok((header.to_string(), column.to_string()))
+        }
+        _ => Err("expected `HEADER=COLUMN`".to_string()),
+    }
+}
+
+/// Parses `ID=BASE64` pair of the `--key` option.
+fn parse_key(value: &str) -> Result<(String, Key), String> {
+    let Some((id, key)) = value.split_once('=').filter(|(id, _)| !id.is_empty()) else {
+        return Err("expected `ID=BASE64`".to_string());
+    };
+    let key = BASE64
+        .decode(key)
+        .map_err(|err| format!("key is not base64 encoded: {err}"))?
+        .try_into()
+        .map_err(|key: Vec<u8>| format!("expected 32 bytes key, got {} bytes", key.len()))?;
+
+    Ok((id.to_string(), key))
+}
+
+/// Builds key provider of the `--key` and `--current-key` options. Returns `None`, if there are
+/// no keys, so payloads are not decrypted.
+fn static_keys(
+    keys: Vec<(String, Key)>,
+    current_key: Option<String>,
+) -> anyhow::Result<Option<StaticKeys>> {
+    let Some(current_key) = current_key.or_else(|| keys.first().map(|(id, _)| id.clone())) else {
+        return Ok(None);
+    };
+    let Some(&(_, current)) = keys.iter().find(|(id, _)| *id == current_key) else {
+        bail!("there is no `--key` with id `{current_key}`");
+    };
+
+    let provider = StaticKeys::new(current_key, current);
+    Ok(Some(
+        keys.into_iter()
+            .fold(provider, |provider, (id, key)| provider.with_key(id, key)),
+    ))
+}
+
+/// Prints event of the row, decoded the same way the relay does it.
+fn print_row(outbox: &Outbox, row: EventRow) {
+    let sequence = row.sequence;
+    let id = row.id;
+
+    match outbox.event_from_row(row) {
+        Ok(event) => print_event(&event),
+        Err(err) => println!("#{sequence} {id}\n  can't decode event: {err}\n"),
+    }
+}
+
+/// Prints dead letter along with its event.
+fn print_dead_letter(outbox: &Outbox, dead_letter: &DeadLetter) {
+    println!(
+        "Dead letter #{} after {} attempts at {}",
+        dead_letter.sequence, dead_letter.attempts, dead_letter.dead_lettered_at
+    );
+    println!("  origin: {}", dead_letter.origin);
+    if let Some(handler) = &dead_letter.handler {
+        println!("  handler: {handler}");
+    }
+    println!("  error: {}", dead_letter.error);
+
+    match outbox.event_from_dead_letter(dead_letter) {
+        Ok(event) => print_event(&event),
+        Err(err) => println!("  can't decode event: {err}\n"),
+    }
+}
+
+fn print_event(event: &Event) {
+    let sequence = event
+        .sequence
+        .map(|sequence| format!("#{sequence} "))
+        .unwrap_or_default();
+
+    println!(
+        "{sequence}{} {} key={}",
+        event.id,
+        event.topic,
+        event.key.as_deref().unwrap_or_default(),
+    );
+    println!("  created at: {}", event.created_at);
+    if let Some(deliver_at) = event.deliver_at {
+        println!("  deliver at: {deliver_at}");
+    }
+    if let Some(expires_at) = event.expires_at {
+        println!("  expires at: {expires_at}");
+    }
+
+    let mut headers: Vec<_> = event.headers.iter().collect();
+    headers.sort();
+    for (name, value) in headers {
+        println!("  {name}: {value}");
+    }
+
+    match std::str::from_utf8(&event.payload) {
+        Ok(payload) => println!("  payload: {payload}\n"),
+        Err(_) => println!("  payload: {} bytes of binary data\n", event.payload.len()),
+    }
+}
@@ -0,0 +1,177 @@
+//! Runs the `panacea` binary against a SQLite database file, that is migrated and filled by the
+//! library, the same way an application does it.
+
+use std::{
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+use sqlx::SqlitePool;
+
+use panacea::outbox::{migrate, Encryption, Outbox, StaticKeys};
+use panacea_types::event;
+
+/// SQLite database file, that is removed when the test ends.
+struct Database {
+    path: PathBuf,
+    pool: SqlitePool,
+}
+
+impl Database {
+    async fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("panacea-cli-{}.db", event::new_id()));
+        let pool = SqlitePool::connect(&format!("sqlite:{}?mode=rwc", path.display()))
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&pool).await.expect("Can't migrate");
+
+        Self { path, pool }
+    }
+
+    /// Runs the CLI with given arguments against the database.
+    fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_panacea"))
+            .arg("--database-url")
+            .arg(format!("sqlite:{}", self.path.display()))
+            .args(args)
+            .output()
+            .expect("Can't run panacea")
+    }
+
+    async fn keys(&self, table: &str) -> Vec<String> {
+        sqlx::query_scalar(&format!("SELECT key FROM {table} ORDER BY sequence"))
+            .fetch_all(&self.pool)
+            .await
+            .expect("Can't fetch keys")
+    }
+
+    /// Stores dead letter of an event with given key.
+    async fn store_dead_letter(&self, key: &str) {
+        let event = event::new(&"user.created", Some(key), &"payload", None);
+
+        Outbox::default()
+            .store_dead_letter(&self.pool, &event, Some("send_greeting"), "oops", 3)
+            .await
+            .expect("Can't store dead letter");
+    }
+
+    /// Stores dead letter of an event with given key, as if the relay has failed to publish it.
+    async fn store_relay_dead_letter(&self, key: &str) {
+        self.store_dead_letter(key).await;
+
+        sqlx::query("UPDATE panacea_dead_letters SET origin = 'relay', handler = NULL")
+            .execute(&self.pool)
+            .await
+            .expect("Can't update dead letter");
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "panacea has failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[async_std::test]
+async fn purges_rows_older_than_given_age() {
+    let db = Database::new().await;
+    for (key, published_at) in [
+        ("old", Some("datetime('now', '-3 days')")),
+        ("recent", Some("datetime('now')")),
+        ("pending", None),
+    ] {
+        panacea::outbox::store(&db.pool, "user.created", Some(key), "payload", None)
+            .await
+            .expect("Can't store event");
+
+        if let Some(published_at) = published_at {
+            sqlx::query(&format!(
+                "UPDATE panacea_outbox SET published_at = {published_at} WHERE key = '{key}'"
+            ))
+            .execute(&db.pool)
+            .await
+            .expect("Can't publish event");
+        }
+    }
+
+    let output = db.run(&["purge", "--older-than", "1d", "--archive"]);
+
+    assert_eq!(stdout(&output), "Purged 1 rows\n");
+    assert_eq!(db.keys("panacea_outbox").await, vec!["recent", "pending"]);
+    assert_eq!(db.keys("panacea_outbox_archive").await, vec!["old"]);
+}
+
+#[async_std::test]
+async fn shows_encrypted_events() {
+    let db = Database::new().await;
+    let id = Outbox::default()
+        .with_encryption(Encryption::new(StaticKeys::new("2024-01", [7; 32])))
+        .store_event(
+            &db.pool,
+            event::new(&"user.created", Some("user"), &"secret", None),
+        )
+        .await
+        .expect("Can't store event");
+    let show = ["show", &id.to_string()];
+
+    assert!(stdout(&db.run(&show)).contains("can't decode event: can't decrypt payload"));
+
+    let key = "--key=2024-01=BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+    assert!(stdout(&db.run(&[&[key][..], &show].concat())).contains("payload: secret\n"));
+
+    // Current key has to be one of the keys
+    let output = db.run(&[&[key, "--current-key=2024-02"][..], &show].concat());
+    assert!(!output.status.success());
+}
+
+#[async_std::test]
+async fn requeues_dead_letters() {
+    let db = Database::new().await;
+    db.store_relay_dead_letter("user").await;
+
+    let output = db.run(&["dead-letter", "requeue", "1"]);
+
+    assert_eq!(stdout(&output), "Requeued dead letter #1\n");
+    assert_eq!(db.keys("panacea_outbox").await, vec!["user"]);
+    assert!(db.keys("panacea_dead_letters").await.is_empty());
+
+    // There is nothing to requeue anymore
+    assert!(!db.run(&["dead-letter", "requeue", "1"]).status.success());
+}
+
+#[async_std::test]
+async fn discards_dead_letters() {
+    let db = Database::new().await;
+    db.store_dead_letter("user").await;
+
+    let output = db.run(&["dead-letter", "discard", "1"]);
+
+    assert_eq!(stdout(&output), "Discarded dead letter #1\n");
+    assert!(db.keys("panacea_outbox").await.is_empty());
+    assert!(db.keys("panacea_dead_letters").await.is_empty());
+
+    assert!(!db.run(&["dead-letter", "discard", "1"]).status.success());
+}
+
+#[async_std::test]
+async fn refuses_to_requeue_dead_letters_of_consumed_events() {
+    let db = Database::new().await;
+    db.store_dead_letter("user").await;
+
+    let output = db.run(&["dead-letter", "requeue", "1"]);
+
+    assert!(!output.status.success());
+    assert!(db.keys("panacea_outbox").await.is_empty());
+    assert_eq!(db.keys("panacea_dead_letters").await, vec!["user"]);
+}
@@ -68,12 +68,11 @@ pub fn handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
         struct #struct_name_ident;
 
         #[async_trait::async_trait]
-        impl panacea_types::Handler for #struct_name_ident {
-            #[cfg(feature = "mysql")]
+        impl<DB: sqlx::Database> panacea_types::Handler<DB> for #struct_name_ident {
             async fn handle(
                 &self,
                 state: &mut panacea_types::state::Container![Send + Sync],
-                tx: &mut sqlx::Transaction<'_, sqlx::MySql>,
+                tx: &mut sqlx::Transaction<'_, DB>,
                 event: &panacea_types::Event,
             ) -> panacea_types::handler::HandlingResult {
                 #(#state_vars)*
@@ -81,28 +80,8 @@ pub fn handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 #fn_name_ident(#handle_fn_args)
             }
 
-            #[cfg(feature = "postgres")]
-            async fn handle<'a>(
-                &self,
-                state: &mut panacea_types::state::Container![Send + Sync],
-                tx: &mut sqlx::Transaction<'a, sqlx::Postgres>,
-                event: &panacea_types::Event,
-            ) -> panacea_types::handler::HandlingResult {
-                #(#state_vars)*
-
-                #fn_name_ident(#handle_fn_args)
-            }
-
-            #[cfg(feature = "sqlite")]
-            async fn handle(
-                &self,
-                state: &mut panacea_types::state::Container![Send + Sync],
-                tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
-                event: &panacea_types::Event,
-            ) -> panacea_types::handler::HandlingResult {
-                #(#state_vars)*
-
-                #fn_name_ident(#handle_fn_args)
+            fn name(&self) -> &'static str {
+                stringify!(#fn_name_ident)
             }
         }
     }
@@ -131,13 +110,10 @@ pub fn handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
 /// }
 ///
 /// // You can use this function when building a worker with `panacea::Worker::with_handlers_resolver()`.
-/// fn resolver(event: &Event) -> MaybeHandlers {
-///     match event.headers.get("event") {
-///         Some(name) => match name.as_str() {
-///             "user_created" => handlers![send_welcome_email, notify_admins],
-///             _ => None,
-///         },
-///         None => None,
+/// fn resolver(event: &Event) -> MaybeHandlers<sqlx::Sqlite> {
+///     match event.event_type().ok()?.as_deref() {
+///         Some("UserCreated") => handlers![send_welcome_email, notify_admins],
+///         _ => None,
 ///     }
 /// }
 /// ```
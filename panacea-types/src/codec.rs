@@ -0,0 +1,232 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Header, that carries content type of the event payload.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("can't encode payload")]
+    Encoding(#[source] anyhow::Error),
+    #[error("can't decode payload")]
+    Decoding(#[source] anyhow::Error),
+    #[error("unsupported content type `{0}`")]
+    UnsupportedContentType(String),
+}
+
+/// Serialization format of event payloads.
+pub trait Codec {
+    /// Content type, recorded in the [`CONTENT_TYPE_HEADER`] of encoded events.
+    fn content_type(&self) -> &'static str;
+
+    /// Encodes value to payload.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the value can't be encoded.
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error>;
+
+    /// Decodes value from payload.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the payload can't be decoded.
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error>;
+}
+
+/// JSON codec.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+impl Codec for Json {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        serde_json::to_vec(value).map_err(|err| Error::Encoding(err.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(payload).map_err(|err| Error::Decoding(err.into()))
+    }
+}
+
+/// MessagePack codec. Structs are encoded as maps, so fields can be added and reordered.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn content_type(&self) -> &'static str {
+        "application/msgpack"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        rmp_serde::to_vec_named(value).map_err(|err| Error::Encoding(err.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(payload).map_err(|err| Error::Decoding(err.into()))
+    }
+}
+
+/// CBOR codec.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+    fn content_type(&self) -> &'static str {
+        "application/cbor"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut payload = Vec::new();
+        ciborium::ser::into_writer(value, &mut payload)
+            .map_err(|err| Error::Encoding(anyhow::anyhow!("{err}")))?;
+
+        Ok(payload)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error> {
+        ciborium::de::from_reader(payload).map_err(|err| Error::Decoding(anyhow::anyhow!("{err}")))
+    }
+}
+
+/// Bincode codec. It's compact, but not self-describing, so consumers should use the same
+/// type definitions as producers.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn content_type(&self) -> &'static str {
+        "application/x-bincode"
+    }
+
+    fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        bincode::serialize(value).map_err(|err| Error::Encoding(err.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(payload).map_err(|err| Error::Decoding(err.into()))
+    }
+}
+
+/// Decodes payload with the built-in codec, that matches given content type. Only the media type
+/// is matched (case-insensitively), so parameters like `; charset=utf-8` are ignored.
+///
+/// # Errors
+///
+/// Will return an [`Error`] if there is no such codec (or its feature is disabled), or if the
+/// payload can't be decoded.
+pub fn decode<T: DeserializeOwned>(content_type: &str, payload: &[u8]) -> Result<T, Error> {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match media_type.as_str() {
+        "application/json" => Json.decode(payload),
+        #[cfg(feature = "msgpack")]
+        "application/msgpack" => MessagePack.decode(payload),
+        #[cfg(feature = "cbor")]
+        "application/cbor" => Cbor.decode(payload),
+        #[cfg(feature = "bincode")]
+        "application/x-bincode" => Bincode.decode(payload),
+        _ => Err(Error::UnsupportedContentType(content_type.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::event;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct UserCreated {
+        id: u64,
+        email: String,
+    }
+
+    fn user() -> UserCreated {
+        UserCreated {
+            id: 42,
+            email: "user@example.com".to_string(),
+        }
+    }
+
+    fn assert_round_trip<C: Codec>(codec: &C) {
+        let event = event::new_typed(codec, &"user.created", Some(42), &user(), None)
+            .expect("Can't encode event");
+
+        assert_eq!(
+            event.headers.get(CONTENT_TYPE_HEADER).map(String::as_str),
+            Some(codec.content_type())
+        );
+        assert_eq!(event.decode::<UserCreated>().expect("Can't decode"), user());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        assert_round_trip(&Json);
+    }
+
+    #[test]
+    fn msgpack_round_trip() {
+        assert_round_trip(&MessagePack);
+    }
+
+    #[test]
+    fn cbor_round_trip() {
+        assert_round_trip(&Cbor);
+    }
+
+    #[test]
+    fn bincode_round_trip() {
+        assert_round_trip(&Bincode);
+    }
+
+    #[test]
+    fn decodes_parameterized_content_type() {
+        let event = event::new_typed(&Json, &"user.created", Some(42), &user(), None)
+            .expect("Can't encode event")
+            .with_content_type("Application/JSON; charset=utf-8")
+            .expect("Can't set content type");
+
+        assert_eq!(event.decode::<UserCreated>().expect("Can't decode"), user());
+    }
+
+    #[test]
+    fn decodes_events_without_content_type_as_json() {
+        let event = event::new(
+            &"user.created",
+            Some(42),
+            &r#"{"id":42,"email":"user@example.com"}"#,
+            None,
+        );
+
+        assert_eq!(event.decode::<UserCreated>().expect("Can't decode"), user());
+    }
+
+    #[test]
+    fn refuses_unsupported_content_type() {
+        let mut event = event::new(&"user.created", Some(42), &"<user/>", None);
+        event.headers.insert(
+            CONTENT_TYPE_HEADER.to_string(),
+            "application/xml".to_string(),
+        );
+
+        assert!(matches!(
+            event.decode::<UserCreated>(),
+            Err(Error::UnsupportedContentType(_))
+        ));
+    }
+}
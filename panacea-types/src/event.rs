@@ -1,5 +1,45 @@
 use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::codec::{self, Codec, CONTENT_TYPE_HEADER};
+use crate::header::{CorrelationId, ExpiresAt, Header};
+
+/// Header, that carries event id when the event is published.
+pub const ID_HEADER: &str = "event-id";
+
+/// Header, that carries type of the event, e.g. to tell apart events of a single topic.
+pub const EVENT_TYPE_HEADER: &str = "event-type";
+
+/// Header, that carries expiration time of the event (in RFC 3339 format) when the event is
+/// published.
+pub const EXPIRES_AT_HEADER: &str = "expires-at";
+
+/// Header, that carries compression algorithm of the event payload, while it's stored in the
+/// outbox.
+pub const CONTENT_ENCODING_HEADER: &str = "content-encoding";
+
+/// Header, that carries id of the key, the data key of encrypted event payload is wrapped with.
+pub const ENCRYPTION_KEY_ID_HEADER: &str = "encryption-key-id";
+
+/// Header, that carries wrapped (encrypted) data key of encrypted event payload.
+pub const ENCRYPTION_DATA_KEY_HEADER: &str = "encryption-data-key";
+
+/// Header, that carries [W3C trace context](https://www.w3.org/TR/trace-context/) of the span,
+/// the event was stored within.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Header, that carries vendor-specific part of the W3C trace context, along with the
+/// [`TRACEPARENT_HEADER`].
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// Header, that carries id shared by all events of a causal chain, i.e. id of the event, that
+/// started the chain.
+pub const CORRELATION_ID_HEADER: &str = "correlation-id";
+
+/// Header, that carries id of the event, handling of which has produced the event.
+pub const CAUSATION_ID_HEADER: &str = "causation-id";
 
 /// Types that can be represented as a reference to a bytes array.
 pub trait AsBytesRef {
@@ -34,17 +74,34 @@ impl AsBytesRef for Vec<u8> {
 pub enum Error {
     #[error("malformed headers")]
     MalformedHeaders(serde_json::Error),
+    #[error("unsupported content encoding `{0}`")]
+    UnsupportedContentEncoding(String),
+    #[error("can't decompress payload")]
+    Decompression(std::io::Error),
+    #[error("can't decrypt payload")]
+    Decryption(#[source] anyhow::Error),
 }
 
 pub type Headers = HashMap<String, String>;
 
 #[derive(Debug, Default, Clone)]
 pub struct Event {
+    /// Unique event id (UUIDv7, so ids are roughly ordered by creation time).
+    pub id: Uuid,
+    /// Position of the event in the outbox table. `None` until the event is stored.
+    pub sequence: Option<i64>,
     pub topic: String,
     pub key: Option<String>,
     pub payload: Vec<u8>,
     pub headers: Headers,
     pub created_at: DateTime<Utc>,
+    /// Time, before which the event should not be published. Published right away, if `None`.
+    pub deliver_at: Option<DateTime<Utc>>,
+    /// Time, after which the event is stale, so it should be discarded rather than delivered.
+    /// Never expires, if `None`.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Identifies the logical event, so storing it again (e.g. on retry) is a no-op.
+    pub idempotency_key: Option<String>,
 }
 
 pub fn new<K, P, T>(topic: &T, key: Option<K>, payload: &P, headers: Option<Headers>) -> Event
@@ -54,6 +111,7 @@ where
     T: ToString,
 {
     Event {
+        id: new_id(),
         topic: topic.to_string(),
         key: Some(key.unwrap_or_default().to_string()),
         payload: payload.as_bytes_ref().to_vec(),
@@ -62,3 +120,180 @@ where
         ..Default::default()
     }
 }
+
+/// Constructs [`Event`] with given value encoded by the codec, recording codec's content type
+/// in the [`CONTENT_TYPE_HEADER`].
+///
+/// # Errors
+///
+/// Will return a [`codec::Error`] if the value can't be encoded.
+pub fn new_typed<C, K, T, V>(
+    codec: &C,
+    topic: &T,
+    key: Option<K>,
+    value: &V,
+    headers: Option<Headers>,
+) -> Result<Event, codec::Error>
+where
+    C: Codec,
+    K: ToString + Default,
+    T: ToString,
+    V: Serialize + ?Sized,
+{
+    let mut headers = headers.unwrap_or_default();
+    headers.insert(
+        CONTENT_TYPE_HEADER.to_string(),
+        codec.content_type().to_string(),
+    );
+
+    Ok(new(topic, key, &codec.encode(value)?, Some(headers)))
+}
+
+impl Event {
+    /// Schedules the event, so it's not published before given time.
+    #[must_use]
+    pub fn with_deliver_at(mut self, deliver_at: DateTime<Utc>) -> Self {
+        self.deliver_at = Some(deliver_at);
+
+        self
+    }
+
+    /// Sets time, after which the event is discarded rather than delivered.
+    #[must_use]
+    pub fn with_expires_at(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+
+        self
+    }
+
+    /// Sets key, that identifies the logical event, so it's stored to the outbox only once.
+    #[must_use]
+    pub fn with_idempotency_key(mut self, idempotency_key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(idempotency_key.into());
+
+        self
+    }
+
+    /// Links the event to the event, handling of which has produced it: sets the
+    /// [`CAUSATION_ID_HEADER`] to id of the cause, and inherits its [`CORRELATION_ID_HEADER`]
+    /// (or starts the chain with id of the cause, if it has none, or if it's not a UUID, so
+    /// malformed ids don't spread through the chain). Headers, that are already set, are kept.
+    #[must_use]
+    pub fn caused_by(mut self, cause: &Event) -> Self {
+        let correlation_id = cause
+            .headers
+            .get(CORRELATION_ID_HEADER)
+            .and_then(|value| CorrelationId::parse(value).ok())
+            .map_or(cause.id, |correlation_id| correlation_id.0)
+            .to_string();
+
+        self.headers
+            .entry(CORRELATION_ID_HEADER.to_string())
+            .or_insert(correlation_id);
+        self.headers
+            .entry(CAUSATION_ID_HEADER.to_string())
+            .or_insert_with(|| cause.id.to_string());
+
+        self
+    }
+
+    /// Returns whether the event has expired by given time, according to its
+    /// [`Event::expires_at`] or the [`EXPIRES_AT_HEADER`] (e.g. for events, that are received
+    /// from a broker).
+    #[must_use]
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        let expires_at = self.expires_at.or_else(|| {
+            self.header::<ExpiresAt>()
+                .ok()
+                .flatten()
+                .map(|expires_at| expires_at.0)
+        });
+
+        expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Decodes payload with the codec, recorded in the [`CONTENT_TYPE_HEADER`]. Events without
+    /// the header are decoded as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Will return a [`codec::Error`] if the content type is not supported, or if the payload
+    /// can't be decoded.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, codec::Error> {
+        let content_type = self
+            .headers
+            .get(CONTENT_TYPE_HEADER)
+            .map_or(codec::Json.content_type(), String::as_str);
+
+        codec::decode(content_type, &self.payload)
+    }
+
+    /// Decodes payload with given codec, regardless of the [`CONTENT_TYPE_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return a [`codec::Error`] if the payload can't be decoded.
+    pub fn decode_with<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T, codec::Error> {
+        codec.decode(&self.payload)
+    }
+}
+
+/// Generates new unique event id.
+#[must_use]
+pub fn new_id() -> Uuid {
+    Uuid::now_v7()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn expires_by_expires_at_or_header() {
+        let now = Utc::now();
+        let event = new(&"otp.sent", Some("user"), &"123456", None);
+        assert!(!event.is_expired_at(now));
+
+        let expiring = event.clone().with_expires_at(now - Duration::seconds(1));
+        assert!(expiring.is_expired_at(now));
+        assert!(!expiring.is_expired_at(now - Duration::minutes(1)));
+
+        let mut received = event;
+        received.headers.insert(
+            EXPIRES_AT_HEADER.to_string(),
+            (now - Duration::seconds(1)).to_rfc3339(),
+        );
+        assert!(received.is_expired_at(now));
+    }
+
+    #[test]
+    fn caused_by_links_events_of_causal_chain() {
+        let created = new(&"user.created", Some("user"), &"{}", None);
+        let greeted = new(&"greeting.sent", Some("user"), &"Hello!", None).caused_by(&created);
+        let logged = new(&"greeting.logged", Some("user"), &"{}", None).caused_by(&greeted);
+
+        let created_id = created.id.to_string();
+        assert_eq!(greeted.headers[CORRELATION_ID_HEADER], created_id);
+        assert_eq!(greeted.headers[CAUSATION_ID_HEADER], created_id);
+        assert_eq!(logged.headers[CORRELATION_ID_HEADER], created_id);
+        assert_eq!(logged.headers[CAUSATION_ID_HEADER], greeted.id.to_string());
+    }
+
+    #[test]
+    fn caused_by_restarts_chain_on_malformed_correlation_id() {
+        let mut received = new(&"user.created", Some("user"), &"{}", None);
+        received
+            .headers
+            .insert(CORRELATION_ID_HEADER.to_string(), "request-1".to_string());
+        let greeted = new(&"greeting.sent", Some("user"), &"Hello!", None).caused_by(&received);
+
+        assert_eq!(
+            greeted
+                .correlation_id()
+                .expect("Can't parse correlation id"),
+            Some(received.id)
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use crate::event::Event;
-use state::Container;
 
-#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+#[cfg(feature = "sqlx")]
 use async_trait::async_trait;
+#[cfg(feature = "sqlx")]
+use state::Container;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -11,41 +12,13 @@ pub enum Error {
 }
 
 pub type HandlingResult = Result<Option<Vec<Event>>, Error>;
-pub type MaybeHandlers = Option<Vec<Box<dyn Handler + Send + Sync>>>;
+#[cfg(feature = "sqlx")]
+pub type MaybeHandlers<DB> = Option<Vec<Box<dyn Handler<DB> + Send + Sync>>>;
 
-#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+#[cfg(feature = "sqlx")]
 #[async_trait]
-pub trait Handler: Send {
-    #[cfg(feature = "postgres")]
-    /// Accepts sqlx postresql transaction and an event.
-    /// Returns a list of events to be published.
-    ///
-    /// # Errors
-    ///
-    /// Will return an [`Error`] if there is any error occurs when handling an event.
-    async fn handle<'a>(
-        &self,
-        state: &mut Container![Send + Sync],
-        tx: &mut sqlx::Transaction<'a, sqlx::Postgres>,
-        event: &Event,
-    ) -> HandlingResult;
-
-    #[cfg(feature = "mysql")]
-    /// Accepts sqlx mysql transaction and an event.
-    /// Returns a list of events to be published.
-    ///
-    /// # Errors
-    ///
-    /// Will return an [`Error`] if there is any error occurs when handling an event.
-    async fn handle<'a>(
-        &self,
-        state: &mut Container![Send + Sync],
-        tx: &mut sqlx::Transaction<'a, sqlx::MySql>,
-        event: &Event,
-    ) -> HandlingResult;
-
-    #[cfg(feature = "sqlite")]
-    /// Accepts sqlx sqlite transaction and an event.
+pub trait Handler<DB: sqlx::Database>: Send {
+    /// Accepts sqlx transaction and an event.
     /// Returns a list of events to be published.
     ///
     /// # Errors
@@ -54,7 +27,12 @@ pub trait Handler: Send {
     async fn handle(
         &self,
         state: &mut Container![Send + Sync],
-        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        tx: &mut sqlx::Transaction<'_, DB>,
         event: &Event,
     ) -> HandlingResult;
+
+    /// Returns name of the handler, e.g. for recording it along with failed events.
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
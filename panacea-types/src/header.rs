@@ -0,0 +1,309 @@
+//! Typed access to the well-known event headers.
+//!
+//! Every well-known header has a type, that implements [`Header`], so its value is validated
+//! when it's set, and parsed when it's read (see [`Event::header()`] and
+//! [`Event::with_header()`]). Custom headers are still available through the raw
+//! [`Event::headers`] map.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::codec::CONTENT_TYPE_HEADER;
+use crate::event::{
+    Event, CAUSATION_ID_HEADER, CORRELATION_ID_HEADER, EVENT_TYPE_HEADER, EXPIRES_AT_HEADER,
+    ID_HEADER,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("malformed `{name}` header value `{value}`: {reason}")]
+    Malformed {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+}
+
+/// Well-known header with a typed value.
+pub trait Header: Sized {
+    /// Name of the header in the [`Event::headers`] map.
+    const NAME: &'static str;
+
+    /// Parses value of the header.
+    ///
+    /// # Errors
+    ///
+    /// Will return a reason, if the value is malformed.
+    fn parse(value: &str) -> Result<Self, String>;
+
+    /// Formats value of the header.
+    fn format(&self) -> String;
+}
+
+/// Type of the event, e.g. to tell apart events of a single topic. Carried by the
+/// [`EVENT_TYPE_HEADER`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventType(pub String);
+
+impl Header for EventType {
+    const NAME: &'static str = EVENT_TYPE_HEADER;
+
+    fn parse(value: &str) -> Result<Self, String> {
+        if value.is_empty() || value.contains(char::is_whitespace) {
+            return Err("expected non-empty value without whitespaces".to_string());
+        }
+
+        Ok(Self(value.to_string()))
+    }
+
+    fn format(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Content type of the event payload, e.g. `application/json`. Carried by the
+/// [`CONTENT_TYPE_HEADER`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType(pub String);
+
+impl Header for ContentType {
+    const NAME: &'static str = CONTENT_TYPE_HEADER;
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let essence = value.split(';').next().unwrap_or_default().trim();
+        match essence.split_once('/') {
+            Some((kind, subtype))
+                if !kind.is_empty() && !subtype.is_empty() && !subtype.contains('/') =>
+            {
+                Ok(Self(value.to_string()))
+            }
+            _ => Err("expected `type/subtype`".to_string()),
+        }
+    }
+
+    fn format(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// Id of the event, as it's published. Carried by the [`ID_HEADER`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventId(pub Uuid);
+
+/// Id shared by all events of a causal chain. Carried by the [`CORRELATION_ID_HEADER`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorrelationId(pub Uuid);
+
+/// Id of the event, handling of which has produced the event. Carried by the
+/// [`CAUSATION_ID_HEADER`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CausationId(pub Uuid);
+
+macro_rules! uuid_header {
+    ($type:ident, $name:expr) => {
+        impl Header for $type {
+            const NAME: &'static str = $name;
+
+            fn parse(value: &str) -> Result<Self, String> {
+                Uuid::parse_str(value)
+                    .map(Self)
+                    .map_err(|err| err.to_string())
+            }
+
+            fn format(&self) -> String {
+                self.0.to_string()
+            }
+        }
+    };
+}
+
+uuid_header!(EventId, ID_HEADER);
+uuid_header!(CorrelationId, CORRELATION_ID_HEADER);
+uuid_header!(CausationId, CAUSATION_ID_HEADER);
+
+/// Time, after which the event is stale. Carried by the [`EXPIRES_AT_HEADER`] in RFC 3339
+/// format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiresAt(pub DateTime<Utc>);
+
+impl Header for ExpiresAt {
+    const NAME: &'static str = EXPIRES_AT_HEADER;
+
+    fn parse(value: &str) -> Result<Self, String> {
+        DateTime::parse_from_rfc3339(value)
+            .map(|expires_at| Self(expires_at.with_timezone(&Utc)))
+            .map_err(|err| err.to_string())
+    }
+
+    fn format(&self) -> String {
+        self.0.to_rfc3339()
+    }
+}
+
+impl Event {
+    /// Returns parsed value of the well-known header, or `None` if the event has no such header.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the value is malformed.
+    pub fn header<H: Header>(&self) -> Result<Option<H>, Error> {
+        self.headers
+            .get(H::NAME)
+            .map(|value| {
+                H::parse(value).map_err(|reason| Error::Malformed {
+                    name: H::NAME,
+                    value: value.clone(),
+                    reason,
+                })
+            })
+            .transpose()
+    }
+
+    /// Sets the well-known header, replacing its previous value.
+    #[must_use]
+    pub fn with_header<H: Header>(mut self, header: &H) -> Self {
+        self.headers.insert(H::NAME.to_string(), header.format());
+
+        self
+    }
+
+    /// Returns value of the [`EVENT_TYPE_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the value is malformed.
+    pub fn event_type(&self) -> Result<Option<String>, Error> {
+        Ok(self.header::<EventType>()?.map(|event_type| event_type.0))
+    }
+
+    /// Sets the [`EVENT_TYPE_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the event type is empty or contains whitespaces.
+    pub fn with_event_type(self, event_type: impl Into<String>) -> Result<Self, Error> {
+        let event_type = validate::<EventType>(event_type.into())?;
+
+        Ok(self.with_header(&event_type))
+    }
+
+    /// Returns value of the [`CONTENT_TYPE_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the value is malformed.
+    pub fn content_type(&self) -> Result<Option<String>, Error> {
+        Ok(self
+            .header::<ContentType>()?
+            .map(|content_type| content_type.0))
+    }
+
+    /// Sets the [`CONTENT_TYPE_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the content type is not `type/subtype`.
+    pub fn with_content_type(self, content_type: impl Into<String>) -> Result<Self, Error> {
+        let content_type = validate::<ContentType>(content_type.into())?;
+
+        Ok(self.with_header(&content_type))
+    }
+
+    /// Returns value of the [`CORRELATION_ID_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the value is not a UUID.
+    pub fn correlation_id(&self) -> Result<Option<Uuid>, Error> {
+        Ok(self.header::<CorrelationId>()?.map(|id| id.0))
+    }
+
+    /// Sets the [`CORRELATION_ID_HEADER`].
+    #[must_use]
+    pub fn with_correlation_id(self, correlation_id: Uuid) -> Self {
+        self.with_header(&CorrelationId(correlation_id))
+    }
+
+    /// Returns value of the [`CAUSATION_ID_HEADER`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the value is not a UUID.
+    pub fn causation_id(&self) -> Result<Option<Uuid>, Error> {
+        Ok(self.header::<CausationId>()?.map(|id| id.0))
+    }
+
+    /// Sets the [`CAUSATION_ID_HEADER`].
+    #[must_use]
+    pub fn with_causation_id(self, causation_id: Uuid) -> Self {
+        self.with_header(&CausationId(causation_id))
+    }
+}
+
+/// Validates header value, that is set from a string.
+fn validate<H: Header>(value: String) -> Result<H, Error> {
+    H::parse(&value).map_err(|reason| Error::Malformed {
+        name: H::NAME,
+        value,
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event;
+
+    fn user_created() -> Event {
+        event::new(&"user.created", Some("user"), &"{}", None)
+    }
+
+    #[test]
+    fn sets_and_parses_well_known_headers() {
+        let correlation_id = event::new_id();
+        let event = user_created()
+            .with_event_type("UserCreated")
+            .expect("Can't set event type")
+            .with_content_type("application/json; charset=utf-8")
+            .expect("Can't set content type")
+            .with_correlation_id(correlation_id);
+
+        assert_eq!(
+            event.event_type().expect("Can't parse event type"),
+            Some("UserCreated".to_string())
+        );
+        assert_eq!(
+            event.content_type().expect("Can't parse content type"),
+            Some("application/json; charset=utf-8".to_string())
+        );
+        assert_eq!(
+            event.correlation_id().expect("Can't parse correlation id"),
+            Some(correlation_id)
+        );
+        assert_eq!(
+            event.causation_id().expect("Can't parse causation id"),
+            None
+        );
+        assert_eq!(event.headers[EVENT_TYPE_HEADER], "UserCreated");
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        assert!(user_created().with_event_type("user created").is_err());
+        assert!(user_created().with_content_type("json").is_err());
+
+        let mut event = user_created();
+        event
+            .headers
+            .insert(CORRELATION_ID_HEADER.to_string(), "request-1".to_string());
+        event
+            .headers
+            .insert(EXPIRES_AT_HEADER.to_string(), "tomorrow".to_string());
+
+        assert!(matches!(
+            event.correlation_id(),
+            Err(Error::Malformed { name, .. }) if name == CORRELATION_ID_HEADER
+        ));
+        assert!(event.header::<ExpiresAt>().is_err());
+    }
+}
@@ -1,15 +1,21 @@
 #![deny(clippy::unwrap_used, unsafe_code)]
 
+pub mod codec;
 pub mod event;
 pub mod handler;
+pub mod header;
+pub mod publisher;
 pub mod state;
 pub mod worker;
 
+pub use codec::Codec;
 pub use event::Event;
+pub use header::Header;
+pub use publisher::Publisher;
 pub use worker::EventSource;
 
-#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
-pub use handler::Handler;
+#[cfg(feature = "sqlx")]
+pub use handler::{Handler, MaybeHandlers};
 
-pub use handler::{HandlingResult, MaybeHandlers};
+pub use handler::HandlingResult;
 // pub use handler::HandlingResult;
@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use async_trait::async_trait;
+
+use crate::event::Event;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
+}
+
+/// Represents destination of events, that are relayed from the outbox table (e.g. message broker).
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    /// Publishes an event.
+    /// Should return only after the event is confirmed by the destination.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the event can't be published.
+    async fn publish(&self, event: &Event) -> Result<(), Error>;
+}
+
+/// [`Publisher`], that collects published events in memory. Useful for tests.
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryPublisher {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl InMemoryPublisher {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns copies of all events, published so far.
+    #[must_use]
+    pub fn events(&self) -> Vec<Event> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[async_trait]
+impl Publisher for InMemoryPublisher {
+    async fn publish(&self, event: &Event) -> Result<(), Error> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(event.clone());
+
+        Ok(())
+    }
+}
@@ -1,7 +1,30 @@
+use std::fmt;
+
 use async_trait::async_trait;
 
 use crate::event::Event;
 
+/// Why the worker has skipped an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// There are no handlers for the event.
+    NoHandlers,
+    /// The event has expired before it could be handled, see [`Event::expires_at`].
+    Expired,
+    /// The event has failed to be handled too many times, and was stored as a dead letter.
+    DeadLettered,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoHandlers => write!(f, "no handlers"),
+            Self::Expired => write!(f, "expired"),
+            Self::DeadLettered => write!(f, "dead-lettered"),
+        }
+    }
+}
+
 /// Represents source of events for feeding the event processing loop.
 #[async_trait]
 pub trait EventSource {
@@ -9,4 +32,10 @@ pub trait EventSource {
     fn failed(&mut self, event: &Event);
     fn succeeded(&mut self, event: &Event);
     fn skipped(&mut self, event: &Event);
+
+    /// Called by the worker instead of [`Self::skipped`], so sources can record why the event
+    /// was skipped. Defaults to [`Self::skipped`].
+    fn skipped_with_reason(&mut self, event: &Event, _reason: SkipReason) {
+        self.skipped(event);
+    }
 }
@@ -3,19 +3,8 @@ use core::time::Duration;
 use sqlx::sqlite::SqlitePool;
 use std::thread;
 
-#[macro_use]
-pub extern crate panacea;
-
-use panacea::outbox::{store, EventRow};
-use panacea_types::{event::Headers, handler::HandlingResult, state::State};
-
-#[handler]
-fn handle_some_stuff(_name: &State<String>) -> HandlingResult {
-    eprintln!("some_state: 123");
-    eprintln!("another_state: {:?}", "234");
-
-    Ok(None)
-}
+use panacea::outbox::{migrate, store, EventRow};
+use panacea_types::event::Headers;
 
 #[async_std::main]
 async fn main() {
@@ -23,7 +12,7 @@ async fn main() {
         .await
         .expect("Can't connect to SQLite");
 
-    create_events_outbox_table(&db_conn).await;
+    migrate(&db_conn).await.expect("Can't migrate outbox table");
 
     // Every second stores new event to the outbox table
     start_producing_events(db_conn.clone(), Duration::from_secs(1)).await;
@@ -82,20 +71,3 @@ async fn print_events_count(db_conn: &SqlitePool) {
         println!("<----- Last event: {event:?}");
     }
 }
-
-async fn create_events_outbox_table(db_conn: &SqlitePool) {
-    sqlx::query(
-        r#"
-            CREATE TABLE panacea_outbox (
-                topic TEXT,
-                key TEXT,
-                payload BLOB,
-                headers TEXT,
-                created_at TEXT
-            )
-        "#,
-    )
-    .execute(db_conn)
-    .await
-    .expect("Can't create `events` table");
-}
@@ -3,6 +3,7 @@ use std::collections::VecDeque;
 use async_trait::async_trait;
 use panacea::worker::Worker;
 use panacea_types::{Event, EventSource, MaybeHandlers};
+use sqlx::Sqlite;
 
 #[derive(Default)]
 struct TestEventSource {
@@ -34,6 +35,6 @@ async fn main() {
         .await;
 }
 
-fn resolver(_event: &Event) -> MaybeHandlers {
+fn resolver(_event: &Event) -> MaybeHandlers<Sqlite> {
     None
 }
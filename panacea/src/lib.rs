@@ -5,6 +5,9 @@ pub mod outbox;
 
 pub mod state;
 
+#[cfg(feature = "tracing")]
+pub mod trace;
+
 #[cfg(feature = "worker")]
 pub mod worker;
 
@@ -0,0 +1,259 @@
+use sqlx::{database::HasArguments, Executor, FromRow, IntoArguments};
+use uuid::Uuid;
+
+use super::{Dialect, Error, EventRow, Outbox};
+
+/// Number of outbox rows in every state.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    /// Rows, that are neither published, expired nor dead-lettered yet.
+    pub pending: i64,
+    pub published: i64,
+    pub expired: i64,
+    /// Rows, that the relay has given up to publish.
+    pub dead_lettered: i64,
+}
+
+/// Operations for inspecting and operating the outbox table, e.g. from the `panacea` CLI.
+impl Outbox {
+    /// Counts outbox rows in every state.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if rows can't be counted.
+    pub async fn counts<'a, E, DB>(&self, executor: E) -> Result<Counts, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> (i64, i64, i64, i64): FromRow<'r, <DB as sqlx::Database>::Row>,
+    {
+        let query = r#"
+            SELECT COUNT(*), COUNT({published_at}), COUNT({expired_at}), COUNT({dead_lettered_at})
+            FROM {outbox}
+        "#;
+
+        let (total, published, expired, dead_lettered): (i64, i64, i64, i64) =
+            sqlx::query_as(&self.sql::<DB>(query))
+                .fetch_one(executor)
+                .await?;
+
+        Ok(Counts {
+            pending: total - published - expired - dead_lettered,
+            published,
+            expired,
+            dead_lettered,
+        })
+    }
+
+    /// Returns up to `limit` latest rows in the order they were stored.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if rows can't be fetched.
+    pub async fn latest_rows<'a, E, DB>(
+        &self,
+        executor: E,
+        limit: u32,
+    ) -> Result<Vec<EventRow>, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        let query = "SELECT {event_columns} FROM {outbox} ORDER BY {sequence} DESC LIMIT $1";
+
+        let mut rows: Vec<EventRow> = sqlx::query_as(&self.sql::<DB>(query))
+            .bind(i64::from(limit))
+            .fetch_all(executor)
+            .await?;
+        rows.reverse();
+
+        Ok(rows)
+    }
+
+    /// Returns up to `limit` rows, stored after the row with given sequence, in the order they
+    /// were stored.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if rows can't be fetched.
+    pub async fn rows_after<'a, E, DB>(
+        &self,
+        executor: E,
+        sequence: i64,
+        limit: u32,
+    ) -> Result<Vec<EventRow>, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        let query = r#"
+            SELECT {event_columns}
+            FROM {outbox}
+            WHERE {sequence} > $1
+            ORDER BY {sequence}
+            LIMIT $2
+        "#;
+
+        let rows = sqlx::query_as(&self.sql::<DB>(query))
+            .bind(sequence)
+            .bind(i64::from(limit))
+            .fetch_all(executor)
+            .await?;
+
+        Ok(rows)
+    }
+
+    /// Returns row of the event with given id, if it's still in the outbox table.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the row can't be fetched.
+    pub async fn find_row<'a, E, DB>(
+        &self,
+        executor: E,
+        id: Uuid,
+    ) -> Result<Option<EventRow>, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> Uuid: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        let query = "SELECT {event_columns} FROM {outbox} WHERE {id} = $1";
+
+        let row = sqlx::query_as(&self.sql::<DB>(query))
+            .bind(id)
+            .fetch_optional(executor)
+            .await?;
+
+        Ok(row)
+    }
+
+    /// Marks published rows of given topic as unpublished, so the relay publishes their events
+    /// again. Only rows, that are still in the outbox table (i.e. not purged yet), are replayed.
+    ///
+    /// Returns the number of replayed rows.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if rows can't be updated.
+    pub async fn replay_topic<'a, E, DB>(&self, executor: E, topic: &str) -> Result<u64, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> String: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        let query = r#"
+            UPDATE {outbox}
+            SET {published_at} = NULL
+            WHERE {topic} = $1 AND {published_at} IS NOT NULL
+        "#;
+
+        let result = sqlx::query(&self.sql::<DB>(query))
+            .bind(topic.to_string())
+            .execute(executor)
+            .await?;
+
+        Ok(DB::rows_affected(&result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Connection, SqliteConnection};
+
+    use super::*;
+    use crate::outbox::{migrate, store};
+
+    async fn connect() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&mut conn).await.expect("Can't migrate");
+
+        for (key, topic) in ["user.created", "user.deleted", "user.created"]
+            .iter()
+            .enumerate()
+        {
+            store(&mut conn, topic, Some(key), "payload", None)
+                .await
+                .expect("Can't store event");
+        }
+
+        conn
+    }
+
+    fn keys(rows: &[EventRow]) -> Vec<Option<&str>> {
+        rows.iter().map(|row| row.key.as_deref()).collect()
+    }
+
+    #[async_std::test]
+    async fn pages_through_rows() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+
+        let latest = outbox
+            .latest_rows(&mut conn, 2)
+            .await
+            .expect("Can't fetch rows");
+        assert_eq!(keys(&latest), vec![Some("1"), Some("2")]);
+
+        let after = outbox
+            .rows_after(&mut conn, latest[0].sequence, 10)
+            .await
+            .expect("Can't fetch rows");
+        assert_eq!(keys(&after), vec![Some("2")]);
+
+        let found = outbox
+            .find_row(&mut conn, latest[1].id)
+            .await
+            .expect("Can't find row");
+        assert_eq!(found.map(|row| row.sequence), Some(latest[1].sequence));
+    }
+
+    #[async_std::test]
+    async fn replays_published_rows_of_topic() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+
+        sqlx::query("UPDATE panacea_outbox SET published_at = datetime('now')")
+            .execute(&mut conn)
+            .await
+            .expect("Can't publish rows");
+        assert_eq!(
+            outbox.counts(&mut conn).await.expect("Can't count rows"),
+            Counts {
+                pending: 0,
+                published: 3,
+                expired: 0,
+                dead_lettered: 0
+            }
+        );
+
+        let replayed = outbox
+            .replay_topic(&mut conn, "user.created")
+            .await
+            .expect("Can't replay topic");
+
+        assert_eq!(replayed, 2);
+        assert_eq!(
+            outbox.counts(&mut conn).await.expect("Can't count rows"),
+            Counts {
+                pending: 2,
+                published: 1,
+                expired: 0,
+                dead_lettered: 0
+            }
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff with jitter between attempts to publish an event, that the publisher has
+/// failed to publish.
+///
+/// The delay before `n`-th retry is `initial_delay * multiplier ^ (n - 1)`, capped by
+/// `max_delay`, and reduced by a random fraction of up to `jitter`, so rows, that have failed
+/// together, are not retried all at once. After `max_attempts` failed attempts the row is
+/// dead-lettered, and the relay doesn't try to publish it anymore.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backoff {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5 * 60),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: 10,
+        }
+    }
+}
+
+impl Backoff {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets delay before the first retry. Defaults to 1 second.
+    #[must_use]
+    pub fn with_initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+
+        self
+    }
+
+    /// Sets maximum delay between retries. Defaults to 5 minutes.
+    #[must_use]
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+
+        self
+    }
+
+    /// Sets how many times the delay grows with every retry. Defaults to 2, values less than 1
+    /// are treated as 1.
+    #[must_use]
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+
+        self
+    }
+
+    /// Sets maximum fraction of the delay, that is randomly cut off. Defaults to 0.2, and is
+    /// clamped to `0.0..=1.0`.
+    #[must_use]
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+
+        self
+    }
+
+    /// Sets number of failed attempts, after which the row is dead-lettered. Defaults to 10.
+    #[must_use]
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+
+        self
+    }
+
+    #[must_use]
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns delay before the next attempt, after given number of failed attempts.
+    #[must_use]
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let exponent = i32::try_from(attempts.saturating_sub(1)).unwrap_or(i32::MAX);
+        let delay = (self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+        let jitter = delay * self.jitter * rand::thread_rng().gen::<f64>();
+
+        // Huge max delays (e.g. `Duration::MAX`) don't survive the round trip through `f64`
+        Duration::try_from_secs_f64(delay - jitter).unwrap_or(self.max_delay)
+    }
+
+    /// Returns whether the row should be dead-lettered after given number of failed attempts.
+    #[must_use]
+    pub fn is_exhausted(&self, attempts: u32) -> bool {
+        attempts >= self.max_attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_delay_up_to_max_delay() {
+        let backoff = Backoff::new()
+            .with_initial_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(10))
+            .with_jitter(0.0);
+
+        let delays: Vec<_> = (1..=5).map(|attempts| backoff.delay(attempts)).collect();
+
+        assert_eq!(delays, [1, 2, 4, 8, 10].map(Duration::from_secs).to_vec());
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn caps_delay_by_huge_max_delay() {
+        let backoff = Backoff::new().with_max_delay(Duration::MAX);
+
+        assert!(backoff.delay(u32::MAX) > Duration::from_secs(u64::MAX / 2));
+        assert_eq!(backoff.with_jitter(0.0).delay(u32::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn cuts_off_jitter_from_delay() {
+        let backoff = Backoff::new()
+            .with_initial_delay(Duration::from_secs(10))
+            .with_jitter(0.5);
+
+        for _ in 0..100 {
+            let delay = backoff.delay(1);
+
+            assert!(delay >= Duration::from_secs(5) && delay <= Duration::from_secs(10));
+        }
+    }
+}
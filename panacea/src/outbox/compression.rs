@@ -0,0 +1,182 @@
+use std::{collections::HashSet, io};
+
+use panacea_types::Event;
+
+/// Compression algorithm of event payloads, recorded in the
+/// [`panacea_types::event::CONTENT_ENCODING_HEADER`] of compressed events.
+///
+/// Algorithms are enabled with `gzip` and `zstd` features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    /// Returns name of the encoding, used as the header value.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Returns encoding with given name, if there is one.
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(Self::Gzip),
+            "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Compresses payload.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`io::Error`] if the payload can't be compressed, or if feature of the
+    /// encoding is disabled.
+    pub fn compress(self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                use io::Write;
+
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(payload)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::encode_all(payload, zstd::DEFAULT_COMPRESSION_LEVEL),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    /// Decompresses payload.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`io::Error`] if the payload can't be decompressed, or if feature of the
+    /// encoding is disabled.
+    pub fn decompress(self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Self::Gzip => {
+                use io::Read;
+
+                let mut decompressed = Vec::new();
+                flate2::read::GzDecoder::new(payload).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::decode_all(payload),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn disabled(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("`{}` feature of panacea is disabled", self.name()),
+        )
+    }
+}
+
+/// Describes which event payloads are compressed when stored to the outbox table.
+///
+/// Payloads are compressed if their topic is one of [`Self::with_topics`] (or any topic, if they
+/// are not set), and their size is at least [`Self::with_min_size`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    /// Compression algorithm.
+    encoding: Encoding,
+    /// Minimum size of payloads to compress, in bytes.
+    min_size: usize,
+    /// Topics of events to compress. Events of any topic are compressed, if empty.
+    topics: HashSet<String>,
+}
+
+impl Compression {
+    #[must_use]
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            min_size: 0,
+            topics: HashSet::new(),
+        }
+    }
+
+    /// Sets minimum size of payloads to compress, in bytes. Defaults to 0.
+    #[must_use]
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+
+        self
+    }
+
+    /// Sets topics of events to compress. Events of any topic are compressed by default.
+    #[must_use]
+    pub fn with_topics<I, T>(mut self, topics: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.topics = topics.into_iter().map(Into::into).collect();
+
+        self
+    }
+
+    #[must_use]
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Returns whether payload of the event should be compressed.
+    pub(crate) fn applies_to(&self, event: &Event) -> bool {
+        event.payload.len() >= self.min_size
+            && (self.topics.is_empty() || self.topics.contains(&event.topic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use panacea_types::event;
+
+    #[test]
+    fn round_trips_payloads() {
+        let payload = "Hello, Panacea! ".repeat(100);
+
+        for encoding in [Encoding::Gzip, Encoding::Zstd] {
+            let compressed = encoding
+                .compress(payload.as_bytes())
+                .expect("Can't compress");
+
+            assert!(compressed.len() < payload.len());
+            assert_eq!(
+                encoding.decompress(&compressed).expect("Can't decompress"),
+                payload.as_bytes()
+            );
+            assert_eq!(Encoding::from_name(encoding.name()), Some(encoding));
+        }
+    }
+
+    #[test]
+    fn applies_to_configured_topics_and_sizes() {
+        let compression = Compression::new(Encoding::Gzip)
+            .with_min_size(10)
+            .with_topics(["documents"]);
+
+        let event = |topic, payload: &str| event::new(&topic, None::<String>, &payload, None);
+
+        assert!(compression.applies_to(&event("documents", "large document")));
+        assert!(!compression.applies_to(&event("documents", "small")));
+        assert!(!compression.applies_to(&event("users", "large document")));
+    }
+}
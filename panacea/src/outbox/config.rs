@@ -0,0 +1,462 @@
+use panacea_types::event::EVENT_TYPE_HEADER;
+
+use super::{Compression, Dialect};
+
+/// Names of the outbox table columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Columns {
+    pub sequence: String,
+    pub id: String,
+    pub topic: String,
+    pub key: String,
+    pub payload: String,
+    pub headers: String,
+    pub created_at: String,
+    pub published_at: String,
+    pub claimed_by: String,
+    pub claimed_until: String,
+    pub deliver_at: String,
+    pub expires_at: String,
+    pub expired_at: String,
+    pub attempts: String,
+    pub last_error: String,
+    pub next_attempt_at: String,
+    pub dead_lettered_at: String,
+    pub idempotency_key: String,
+    /// Column, that carries the [`EVENT_TYPE_HEADER`] of events.
+    pub event_type: String,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            sequence: "sequence".to_string(),
+            id: "id".to_string(),
+            topic: "topic".to_string(),
+            key: "key".to_string(),
+            payload: "payload".to_string(),
+            headers: "headers".to_string(),
+            created_at: "created_at".to_string(),
+            published_at: "published_at".to_string(),
+            claimed_by: "claimed_by".to_string(),
+            claimed_until: "claimed_until".to_string(),
+            deliver_at: "deliver_at".to_string(),
+            expires_at: "expires_at".to_string(),
+            expired_at: "expired_at".to_string(),
+            attempts: "attempts".to_string(),
+            last_error: "last_error".to_string(),
+            next_attempt_at: "next_attempt_at".to_string(),
+            dead_lettered_at: "dead_lettered_at".to_string(),
+            idempotency_key: "idempotency_key".to_string(),
+            event_type: "event_type".to_string(),
+        }
+    }
+}
+
+impl Columns {
+    /// Returns column names of the layout, that the [Debezium Outbox Event Router] expects:
+    /// topic of the event is stored to the `aggregatetype` column, its key to the `aggregateid`
+    /// column, and its [`EVENT_TYPE_HEADER`] to the `type` column, along with `id` and
+    /// `payload`. So the same table can be shipped either by Debezium, or by [`super::Relay`].
+    ///
+    /// Other headers can be stored to additional columns with [`Outbox::with_header_column()`].
+    ///
+    /// [Debezium Outbox Event Router]: https://debezium.io/documentation/reference/stable/transformations/outbox-event-router.html
+    #[must_use]
+    pub fn debezium() -> Self {
+        Self {
+            topic: "aggregatetype".to_string(),
+            key: "aggregateid".to_string(),
+            event_type: "type".to_string(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Describes where outbox table lives and how its columns are named.
+///
+/// Every outbox function, [`super::Relay`], [`super::Retention`] and the worker go through it,
+/// so several outboxes (e.g. of different bounded contexts) can share the same database.
+/// The archive, dead letters and migrations tables are named after the outbox table, with
+/// `_archive`, `_dead_letters` and `_migrations` suffixes. The dead letters table replaces the
+/// `_outbox` suffix of the outbox table instead, so the default outbox keeps its dead letters
+/// in `panacea_dead_letters`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outbox {
+    /// Database schema (or attached database name for SQLite). Default one, if not set.
+    schema: Option<String>,
+    /// Outbox table name.
+    table: String,
+    /// Outbox table columns.
+    columns: Columns,
+    /// Compression of stored event payloads. Payloads are stored as is, if not set.
+    compression: Option<Compression>,
+    /// Encryption of stored event payloads. Payloads are stored in plaintext, if not set.
+    #[cfg(feature = "encryption")]
+    encryption: Option<super::Encryption>,
+    /// Additional columns, that carry headers of events, as pairs of header and column names.
+    header_columns: Vec<(String, String)>,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            table: "panacea_outbox".to_string(),
+            columns: Columns::default(),
+            compression: None,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+            header_columns: Vec::new(),
+        }
+    }
+}
+
+impl Outbox {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets outbox table name. Defaults to `panacea_outbox`.
+    #[must_use]
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+
+        self
+    }
+
+    /// Sets database schema of the outbox table.
+    #[must_use]
+    pub fn with_schema(mut self, schema: impl Into<String>) -> Self {
+        self.schema = Some(schema.into());
+
+        self
+    }
+
+    /// Sets outbox table column names.
+    #[must_use]
+    pub fn with_columns(mut self, columns: Columns) -> Self {
+        self.columns = columns;
+
+        self
+    }
+
+    /// Sets compression of stored event payloads. Compressed events are marked with the
+    /// [`panacea_types::event::CONTENT_ENCODING_HEADER`], and decompressed when they are read
+    /// back from the outbox table, so relayed events carry original payloads.
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+
+        self
+    }
+
+    /// Sets encryption of stored event payloads. Payloads are compressed (if configured) before
+    /// they are encrypted, and decrypted when they are read back from the outbox table.
+    #[cfg(feature = "encryption")]
+    #[must_use]
+    pub fn with_encryption(mut self, encryption: super::Encryption) -> Self {
+        self.encryption = Some(encryption);
+
+        self
+    }
+
+    /// Adds column, that carries given header of events (e.g. for Debezium to place it into the
+    /// message envelope). The column is not created by migrations, so it should be added to the
+    /// outbox table as a nullable text column (and to the archive table, if rows are archived by
+    /// [`super::Retention`]). Headers are still stored to the headers column as
+    /// well, so events are read back the same way.
+    #[must_use]
+    pub fn with_header_column(
+        mut self,
+        header: impl Into<String>,
+        column: impl Into<String>,
+    ) -> Self {
+        self.header_columns.push((header.into(), column.into()));
+
+        self
+    }
+
+    #[must_use]
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    #[must_use]
+    pub fn schema(&self) -> Option<&str> {
+        self.schema.as_deref()
+    }
+
+    #[must_use]
+    pub fn columns(&self) -> &Columns {
+        &self.columns
+    }
+
+    #[must_use]
+    pub fn compression(&self) -> Option<&Compression> {
+        self.compression.as_ref()
+    }
+
+    #[cfg(feature = "encryption")]
+    #[must_use]
+    pub fn encryption(&self) -> Option<&super::Encryption> {
+        self.encryption.as_ref()
+    }
+
+    #[must_use]
+    pub fn header_columns(&self) -> &[(String, String)] {
+        &self.header_columns
+    }
+
+    /// Returns names of headers, that are stored to their own columns, in order of the
+    /// `{header_columns}` token.
+    pub(crate) fn column_headers(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(EVENT_TYPE_HEADER).chain(
+            self.header_columns
+                .iter()
+                .map(|(header, _)| header.as_str()),
+        )
+    }
+
+    /// Returns channel, that is notified with `pg_notify` when events are stored to the outbox
+    /// table. Notifications are delivered when the surrounding transaction is committed, and
+    /// collapsed into a single one per transaction. See [`super::Relay::with_notifications`].
+    #[cfg(feature = "postgres")]
+    #[must_use]
+    pub fn notify_channel(&self) -> String {
+        match &self.schema {
+            Some(schema) => format!("{schema}.{}", self.table),
+            None => self.table.clone(),
+        }
+    }
+
+    /// Renders SQL template in the dialect of `DB`, replacing `$1`, `$2`, ... with bind parameter
+    /// placeholders (so parameters should be bound in order of their indexes), `{now}` with the
+    /// current timestamp expression, and other `{...}` tokens with quoted identifiers:
+    ///
+    /// - `{outbox}`, `{archive}`, `{dead_letters}`, `{migrations}` – schema-qualified table
+    ///   names;
+    /// - `{outbox_name}`, `{archive_name}` – unqualified table names;
+    /// - `{unpublished_idx}`, `{published_idx}`, `{id_idx}`, `{archive_id_idx}`,
+    ///   `{idempotency_key_idx}` – index names;
+    /// - `{sequence}`, `{id}`, `{topic}` and other [`Columns`] – column names;
+    /// - `{header_columns}` – columns, that carry headers of events (the event type column and
+    ///   ones added with [`Outbox::with_header_column()`]);
+    /// - `{event_columns}` – columns of [`super::EventRow`], aliased to its field names;
+    /// - `{dead_letter_columns}` – columns of [`super::DeadLetter`], aliased to its field names.
+    pub(crate) fn sql<DB: Dialect>(&self, template: &str) -> String {
+        let columns = &self.columns;
+        let quote = DB::quote;
+        let event_columns = [
+            (quote(&columns.sequence), "sequence"),
+            (quote(&columns.id), "id"),
+            (quote(&columns.topic), "topic"),
+            (quote(&columns.key), "key"),
+            (quote(&columns.payload), "payload"),
+            (DB::json_text(&quote(&columns.headers)), "headers"),
+            (quote(&columns.created_at), "created_at"),
+            (quote(&columns.deliver_at), "deliver_at"),
+            (quote(&columns.expires_at), "expires_at"),
+            (quote(&columns.attempts), "attempts"),
+            (quote(&columns.idempotency_key), "idempotency_key"),
+            (quote(&columns.event_type), "event_type"),
+        ]
+        .iter()
+        .map(|(column, field)| format!("{column} AS {}", quote(field)))
+        .collect::<Vec<_>>()
+        .join(", ");
+        let header_columns = std::iter::once(&columns.event_type)
+            .chain(self.header_columns.iter().map(|(_, column)| column))
+            .map(|column| quote(column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let dead_letter_columns = [
+            (quote(&columns.sequence), "sequence"),
+            (quote(&columns.id), "id"),
+            (quote(&columns.topic), "topic"),
+            (quote(&columns.key), "key"),
+            (quote(&columns.payload), "payload"),
+            (DB::json_text(&quote(&columns.headers)), "headers"),
+            (quote(&columns.created_at), "created_at"),
+            (quote(&columns.deliver_at), "deliver_at"),
+            (quote(&columns.expires_at), "expires_at"),
+            (quote(&columns.idempotency_key), "idempotency_key"),
+            (quote(&columns.event_type), "event_type"),
+            (quote(&columns.attempts), "attempts"),
+            (quote(&columns.last_error), "error"),
+            (quote("handler"), "handler"),
+            (quote("origin"), "origin"),
+            (quote(&columns.dead_lettered_at), "dead_lettered_at"),
+        ]
+        .iter()
+        .map(|(column, field)| format!("{column} AS {}", quote(field)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+        let tokens = [
+            ("{now}", DB::NOW.to_string()),
+            ("{outbox}", self.qualified::<DB>(&self.table)),
+            ("{archive}", self.qualified::<DB>(&self.archive_table())),
+            (
+                "{dead_letters}",
+                self.qualified::<DB>(&self.dead_letters_table()),
+            ),
+            (
+                "{migrations}",
+                self.qualified::<DB>(&format!("{}_migrations", self.table)),
+            ),
+            ("{outbox_name}", quote(&self.table)),
+            ("{archive_name}", quote(&self.archive_table())),
+            ("{unpublished_idx}", self.index::<DB>("unpublished")),
+            ("{published_idx}", self.index::<DB>("published")),
+            ("{id_idx}", self.index::<DB>("id")),
+            ("{archive_id_idx}", self.index::<DB>("archive_id")),
+            ("{idempotency_key_idx}", self.index::<DB>("idempotency_key")),
+            ("{header_columns}", header_columns),
+            ("{event_columns}", event_columns),
+            ("{dead_letter_columns}", dead_letter_columns),
+            ("{sequence}", quote(&columns.sequence)),
+            ("{id}", quote(&columns.id)),
+            ("{topic}", quote(&columns.topic)),
+            ("{key}", quote(&columns.key)),
+            ("{payload}", quote(&columns.payload)),
+            ("{headers}", quote(&columns.headers)),
+            ("{created_at}", quote(&columns.created_at)),
+            ("{published_at}", quote(&columns.published_at)),
+            ("{claimed_by}", quote(&columns.claimed_by)),
+            ("{claimed_until}", quote(&columns.claimed_until)),
+            ("{deliver_at}", quote(&columns.deliver_at)),
+            ("{expires_at}", quote(&columns.expires_at)),
+            ("{expired_at}", quote(&columns.expired_at)),
+            ("{attempts}", quote(&columns.attempts)),
+            ("{last_error}", quote(&columns.last_error)),
+            ("{next_attempt_at}", quote(&columns.next_attempt_at)),
+            ("{dead_lettered_at}", quote(&columns.dead_lettered_at)),
+            ("{idempotency_key}", quote(&columns.idempotency_key)),
+            ("{event_type}", quote(&columns.event_type)),
+        ];
+
+        tokens
+            .iter()
+            .fold(placeholders::<DB>(template), |sql, (token, value)| {
+                sql.replace(token, value)
+            })
+    }
+
+    fn archive_table(&self) -> String {
+        format!("{}_archive", self.table)
+    }
+
+    fn dead_letters_table(&self) -> String {
+        let prefix = self.table.strip_suffix("_outbox").unwrap_or(&self.table);
+
+        format!("{prefix}_dead_letters")
+    }
+
+    /// Returns quoted table name, qualified with schema if it's set.
+    fn qualified<DB: Dialect>(&self, table: &str) -> String {
+        match &self.schema {
+            Some(schema) => format!("{}.{}", DB::quote(schema), DB::quote(table)),
+            None => DB::quote(table),
+        }
+    }
+
+    /// Returns quoted index name. Some backends (e.g. SQLite) expect schema to be set on the
+    /// index, rather than on the indexed table, so it's qualified there.
+    fn index<DB: Dialect>(&self, name: &str) -> String {
+        let index = format!("{}_{name}_idx", self.table);
+
+        if DB::SCHEMA_QUALIFIED_INDEXES {
+            self.qualified::<DB>(&index)
+        } else {
+            DB::quote(&index)
+        }
+    }
+}
+
+/// Replaces `$1`, `$2`, ... in SQL template with bind parameter placeholders of `DB`.
+fn placeholders<DB: Dialect>(template: &str) -> String {
+    let mut sql = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(symbol) = chars.next() {
+        if symbol != '$' {
+            sql.push(symbol);
+            continue;
+        }
+
+        let mut index = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            index.push(digit);
+        }
+
+        match index.parse() {
+            Ok(index) => sql.push_str(&DB::placeholder(index)),
+            Err(_) => sql.push('$'),
+        }
+    }
+
+    sql
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Sqlite;
+
+    use super::*;
+
+    #[test]
+    fn renders_default_identifiers() {
+        let outbox = Outbox::default();
+
+        assert_eq!(
+            outbox.sql::<Sqlite>("SELECT {sequence} FROM {outbox} WHERE {published_at} IS NULL"),
+            r#"SELECT "sequence" FROM "panacea_outbox" WHERE "published_at" IS NULL"#
+        );
+        assert_eq!(
+            outbox.sql::<Sqlite>("{archive}; {dead_letters}; {migrations}"),
+            r#""panacea_outbox_archive"; "panacea_dead_letters"; "panacea_outbox_migrations""#
+        );
+    }
+
+    #[test]
+    fn renders_configured_identifiers() {
+        let outbox = Outbox::new()
+            .with_schema("billing")
+            .with_table("outbox")
+            .with_columns(Columns {
+                topic: "event_\"topic\"".to_string(),
+                ..Columns::default()
+            });
+
+        assert_eq!(
+            outbox.sql::<Sqlite>(
+                "SELECT {topic} FROM {outbox}; {migrations}; {dead_letters}; {archive_name}"
+            ),
+            r#"SELECT "event_""topic""" FROM "billing"."outbox"; "billing"."outbox_migrations"; "billing"."outbox_dead_letters"; "outbox_archive""#
+        );
+        assert_eq!(
+            outbox.sql::<Sqlite>("{event_columns}").split(", ").nth(2),
+            Some(r#""event_""topic""" AS "topic""#)
+        );
+    }
+
+    #[test]
+    fn renders_debezium_layout() {
+        let outbox = Outbox::new()
+            .with_columns(Columns::debezium())
+            .with_header_column("traceparent", "tracingspancontext");
+
+        assert_eq!(
+            outbox.sql::<Sqlite>(
+                "INSERT INTO {outbox} ({id}, {topic}, {key}, {payload}, {header_columns})"
+            ),
+            r#"INSERT INTO "panacea_outbox" ("id", "aggregatetype", "aggregateid", "payload", "type", "tracingspancontext")"#
+        );
+        assert_eq!(
+            outbox.column_headers().collect::<Vec<_>>(),
+            ["event-type", "traceparent"]
+        );
+    }
+}
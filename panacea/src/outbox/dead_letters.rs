@@ -0,0 +1,625 @@
+use std::{error::Error as StdError, fmt};
+
+use chrono::{DateTime, Utc};
+use sqlx::{
+    database::HasArguments, Acquire, Connection, Decode, Encode, Executor, FromRow, IntoArguments,
+    Type,
+};
+use uuid::Uuid;
+
+use panacea_types::event::{self, Event, Headers};
+
+use super::{Dialect, EncodedEvent, Error, EventRow, Outbox};
+
+/// Where the dead letter comes from, which defines how it can be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadLetterOrigin {
+    /// The relay has failed to publish a row of the outbox table. Such dead letters are
+    /// requeued to the outbox table.
+    Relay,
+    /// The worker has failed to handle a consumed event. Such dead letters can't be requeued to
+    /// the outbox table, as the relay would publish them to every consumer of the topic again,
+    /// so they should be redelivered to the worker (e.g. by publishing event of the dead letter
+    /// to the worker's queue, see [`Outbox::event_from_dead_letter()`]), or discarded.
+    Worker,
+}
+
+impl DeadLetterOrigin {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Relay => "relay",
+            Self::Worker => "worker",
+        }
+    }
+}
+
+impl fmt::Display for DeadLetterOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for DeadLetterOrigin {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "relay" => Ok(Self::Relay),
+            "worker" => Ok(Self::Worker),
+            _ => Err(value),
+        }
+    }
+}
+
+/// Event, that the relay has failed to publish, or the worker has failed to handle, after all
+/// attempts. Dead letters are stored in the dead letters table of the outbox (e.g.
+/// `panacea_dead_letters`), with payloads as they are stored in the outbox table.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DeadLetter {
+    /// Position of the dead letter in the dead letters table.
+    pub sequence: i64,
+    pub id: Uuid,
+    pub topic: String,
+    pub key: Option<String>,
+    pub payload: Vec<u8>,
+    pub headers: String,
+    pub created_at: DateTime<Utc>,
+    pub deliver_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub idempotency_key: Option<String>,
+    /// Value of the event type column, that carries the [`event::EVENT_TYPE_HEADER`].
+    pub event_type: Option<String>,
+    /// Number of failed attempts to publish or handle the event.
+    pub attempts: i32,
+    /// Error of the last attempt, along with its sources.
+    pub error: String,
+    /// Name of the handler, that has failed to handle the event. Not set, if the relay has
+    /// failed to publish it.
+    pub handler: Option<String>,
+    #[sqlx(try_from = "String")]
+    pub origin: DeadLetterOrigin,
+    pub dead_lettered_at: DateTime<Utc>,
+}
+
+impl Outbox {
+    /// Stores consumed event to the dead letters table, after the worker has failed to handle it
+    /// (see [`DeadLetterOrigin::Worker`]). Payload is compressed and encrypted the same way as in
+    /// the outbox table.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the payload can't be encoded, or if the dead letter can't be
+    /// stored.
+    pub async fn store_dead_letter<'a, E, DB>(
+        &self,
+        executor: E,
+        event: &Event,
+        handler: Option<&str>,
+        error: &str,
+        attempts: u32,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> String: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+        for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB>,
+        for<'q> i64: Type<DB> + Encode<'q, DB>,
+        for<'q> DateTime<Utc>: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+    {
+        let EncodedEvent {
+            id,
+            payload,
+            headers,
+            ..
+        } = self.encode_payload(event)?;
+
+        let insert = r#"
+            INSERT INTO {dead_letters} (
+                {id}, {topic}, {key}, {payload}, {headers}, {created_at}, {deliver_at},
+                {expires_at}, {idempotency_key}, {event_type}, {attempts}, {last_error}, handler,
+                origin
+            ) VALUES ($1, $2, $3, $4, {headers_value}, $6, $7, $8, $9, $10, $11, $12, $13, 'worker')
+        "#
+        .replace("{headers_value}", &DB::json("$5"));
+
+        sqlx::query(&self.sql::<DB>(&insert))
+            .bind(id)
+            .bind(event.topic.clone())
+            .bind(event.key.clone().unwrap_or_default())
+            .bind(payload)
+            .bind(headers)
+            .bind(event.created_at)
+            .bind(event.deliver_at)
+            .bind(event.expires_at)
+            .bind(event.idempotency_key.clone())
+            .bind(event.headers.get(event::EVENT_TYPE_HEADER).cloned())
+            .bind(i64::from(attempts))
+            .bind(error.to_string())
+            .bind(handler.map(ToString::to_string))
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Copies outbox row with given sequence to the dead letters table, once the relay has given
+    /// up to publish it.
+    pub(crate) async fn dead_letter_row<'a, E, DB>(
+        &self,
+        executor: E,
+        sequence: i64,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> i64: Type<DB> + Encode<'q, DB>,
+    {
+        let query = r#"
+            INSERT INTO {dead_letters} (
+                {id}, {topic}, {key}, {payload}, {headers}, {created_at}, {deliver_at},
+                {expires_at}, {idempotency_key}, {event_type}, {attempts}, {last_error}, origin
+            )
+            SELECT
+                {id}, {topic}, {key}, {payload}, {headers}, {created_at}, {deliver_at},
+                {expires_at}, {idempotency_key}, {event_type}, {attempts}, {last_error}, 'relay'
+            FROM {outbox}
+            WHERE {sequence} = $1
+        "#;
+
+        sqlx::query(&self.sql::<DB>(query))
+            .bind(sequence)
+            .execute(executor)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns up to `limit` oldest dead letters.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if dead letters can't be fetched.
+    pub async fn dead_letters<'a, E, DB>(
+        &self,
+        executor: E,
+        limit: u32,
+    ) -> Result<Vec<DeadLetter>, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> DeadLetter: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> i64: Type<DB> + Encode<'q, DB>,
+    {
+        let query = r#"
+            SELECT {dead_letter_columns}
+            FROM {dead_letters}
+            ORDER BY {sequence}
+            LIMIT $1
+        "#;
+
+        let dead_letters = sqlx::query_as(&self.sql::<DB>(query))
+            .bind(i64::from(limit))
+            .fetch_all(executor)
+            .await?;
+
+        Ok(dead_letters)
+    }
+
+    /// Returns event of the dead letter, decoded the same way as events of outbox rows (see
+    /// [`Outbox::event_from_row()`]).
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`event::Error`] if headers are malformed, or if the payload can't be
+    /// decrypted or decompressed.
+    pub fn event_from_dead_letter(&self, dead_letter: &DeadLetter) -> Result<Event, event::Error> {
+        let row = EventRow {
+            sequence: dead_letter.sequence,
+            id: dead_letter.id,
+            topic: dead_letter.topic.clone(),
+            key: dead_letter.key.clone(),
+            payload: dead_letter.payload.clone(),
+            headers: dead_letter.headers.clone(),
+            created_at: dead_letter.created_at,
+            deliver_at: dead_letter.deliver_at,
+            expires_at: dead_letter.expires_at,
+            attempts: dead_letter.attempts,
+            idempotency_key: dead_letter.idempotency_key.clone(),
+            event_type: dead_letter.event_type.clone(),
+        };
+
+        self.event_from_row(row).map(|event| Event {
+            sequence: None,
+            ..event
+        })
+    }
+
+    /// Moves event of the relay's dead letter back to the outbox table, so the relay publishes it
+    /// again from scratch. If the outbox row of the event is still there, it's reset to unpublished
+    /// state, otherwise the event is stored again with its original id, along with the rest of
+    /// its columns. Header columns (see [`Outbox::with_header_column()`]) are filled from the
+    /// stored headers, as they are not kept in the dead letters table.
+    ///
+    /// Returns `false`, if there is no dead letter with given sequence.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the dead letter can't be requeued: e.g. it's the worker's one
+    /// (see [`DeadLetterOrigin`]), or its idempotency key has been taken by another event since
+    /// its outbox row was purged.
+    pub async fn requeue_dead_letter<'a, A, DB>(
+        &self,
+        executor: A,
+        sequence: i64,
+    ) -> Result<bool, Error>
+    where
+        A: Acquire<'a, Database = DB>,
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> i64: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        for<'q> String: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        usize: sqlx::ColumnIndex<<DB as sqlx::Database>::Row>,
+    {
+        let mut conn = executor.acquire().await?;
+        let mut tx = conn.begin().await?;
+
+        let select = format!(
+            "SELECT {{id}}, {}, {{idempotency_key}}, origin FROM {{dead_letters}} WHERE {{sequence}} = $1",
+            DB::json_text("{headers}")
+        );
+        let dead_letter: Option<(Uuid, String, Option<String>, String)> =
+            sqlx::query_as(&self.sql::<DB>(&select))
+                .bind(sequence)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some((id, headers, idempotency_key, origin)) = dead_letter else {
+            return Ok(false);
+        };
+        if origin != DeadLetterOrigin::Relay.as_str() {
+            return Err(Error::NotRequeueable(sequence));
+        }
+
+        let stored: i64 =
+            sqlx::query_scalar(&self.sql::<DB>("SELECT COUNT(*) FROM {outbox} WHERE {id} = $1"))
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        if stored > 0 {
+            let reset = r#"
+                UPDATE {outbox}
+                SET {published_at} = NULL,
+                    {dead_lettered_at} = NULL,
+                    {next_attempt_at} = NULL,
+                    {last_error} = NULL,
+                    {attempts} = 0
+                WHERE {id} = $1
+            "#;
+
+            sqlx::query(&self.sql::<DB>(reset))
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            if let Some(idempotency_key) = idempotency_key {
+                let taken: i64 = sqlx::query_scalar(
+                    &self.sql::<DB>("SELECT COUNT(*) FROM {outbox} WHERE {idempotency_key} = $1"),
+                )
+                .bind(idempotency_key.clone())
+                .fetch_one(&mut *tx)
+                .await?;
+                if taken > 0 {
+                    return Err(Error::IdempotencyKeyTaken(idempotency_key));
+                }
+            }
+
+            // The event type column is kept in the dead letters table, other header columns
+            // are filled from the headers
+            let headers: Headers = serde_json::from_str(&headers)
+                .map_err(|err| Error::EventDecoding(event::Error::MalformedHeaders(err)))?;
+            let header_values: Vec<_> = self
+                .header_columns()
+                .iter()
+                .map(|(header, _)| headers.get(header).cloned())
+                .collect();
+            // Placeholders are numbered in order of appearance, as SQLite and MySQL bind them
+            let header_params: String = (1..=header_values.len())
+                .map(|index| format!(", ${index}"))
+                .collect();
+            let sequence_param = format!("${}", header_values.len() + 1);
+
+            let insert = self.sql::<DB>(
+                &r#"
+                    INSERT INTO {outbox} (
+                        {id}, {topic}, {key}, {payload}, {headers}, {created_at}, {deliver_at},
+                        {expires_at}, {idempotency_key}, {header_columns}
+                    )
+                    SELECT
+                        {id}, {topic}, {key}, {payload}, {headers}, {created_at}, {deliver_at},
+                        {expires_at}, {idempotency_key}, {event_type}{header_params}
+                    FROM {dead_letters}
+                    WHERE {sequence} = {sequence_param}
+                "#
+                .replace("{header_params}", &header_params)
+                .replace("{sequence_param}", &sequence_param),
+            );
+
+            let insert = DB::with_notification(self, insert);
+
+            let mut query = sqlx::query(&insert);
+            for value in header_values {
+                query = query.bind(value);
+            }
+            query.bind(sequence).execute(&mut *tx).await?;
+        }
+
+        sqlx::query(&self.sql::<DB>("DELETE FROM {dead_letters} WHERE {sequence} = $1"))
+            .bind(sequence)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Deletes dead letter with given sequence.
+    ///
+    /// Returns `false`, if there is no such dead letter.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the dead letter can't be deleted.
+    pub async fn discard_dead_letter<'a, E, DB>(
+        &self,
+        executor: E,
+        sequence: i64,
+    ) -> Result<bool, Error>
+    where
+        E: Executor<'a, Database = DB>,
+        DB: Dialect,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> i64: Type<DB> + Encode<'q, DB>,
+    {
+        let result =
+            sqlx::query(&self.sql::<DB>("DELETE FROM {dead_letters} WHERE {sequence} = $1"))
+                .bind(sequence)
+                .execute(executor)
+                .await?;
+
+        Ok(DB::rows_affected(&result) > 0)
+    }
+}
+
+/// Formats error along with its sources, e.g. `can't publish event: broker is unavailable`.
+pub(crate) fn error_chain(err: &dyn StdError) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        chain.push_str(": ");
+        chain.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Connection, SqliteConnection};
+
+    use super::*;
+    use crate::outbox::{migrate, store};
+
+    async fn connect() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&mut conn).await.expect("Can't migrate");
+
+        conn
+    }
+
+    async fn count(conn: &mut SqliteConnection, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+            .fetch_one(conn)
+            .await
+            .expect("Can't count rows")
+    }
+
+    /// Stores event, dead-letters its row, the same way the relay does it, and purges the row.
+    async fn dead_letter_purged_row(conn: &mut SqliteConnection) -> Event {
+        let outbox = Outbox::default();
+        let event = Event {
+            idempotency_key: Some("create-user".to_string()),
+            ..event::new(&"user.created", Some("user"), &"payload", None)
+        }
+        .with_event_type("UserCreated")
+        .expect("Can't set event type");
+
+        outbox
+            .store_event(&mut *conn, event.clone())
+            .await
+            .expect("Can't store event");
+        sqlx::query(
+            "UPDATE panacea_outbox SET attempts = 3, last_error = 'oops', dead_lettered_at = datetime('now')",
+        )
+        .execute(&mut *conn)
+        .await
+        .expect("Can't dead-letter row");
+        outbox
+            .dead_letter_row(&mut *conn, 1)
+            .await
+            .expect("Can't dead-letter row");
+        sqlx::query("DELETE FROM panacea_outbox")
+            .execute(&mut *conn)
+            .await
+            .expect("Can't purge row");
+
+        event
+    }
+
+    #[async_std::test]
+    async fn stores_dead_letters_of_consumed_events() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+        let event = event::new(&"user.created", Some("user"), &"payload", None);
+
+        outbox
+            .store_dead_letter(&mut conn, &event, Some("send_greeting"), "oops", 3)
+            .await
+            .expect("Can't store dead letter");
+
+        let dead_letters = outbox
+            .dead_letters(&mut conn, 10)
+            .await
+            .expect("Can't list dead letters");
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].handler.as_deref(), Some("send_greeting"));
+        assert_eq!(dead_letters[0].origin, DeadLetterOrigin::Worker);
+        assert_eq!(dead_letters[0].attempts, 3);
+        assert_eq!(
+            outbox
+                .event_from_dead_letter(&dead_letters[0])
+                .expect("Can't decode event")
+                .payload,
+            b"payload"
+        );
+
+        // The relay would publish it to every consumer of the topic again
+        assert!(matches!(
+            outbox
+                .requeue_dead_letter(&mut conn, dead_letters[0].sequence)
+                .await,
+            Err(Error::NotRequeueable(_))
+        ));
+        assert_eq!(count(&mut conn, "panacea_outbox").await, 0);
+        assert_eq!(count(&mut conn, "panacea_dead_letters").await, 1);
+    }
+
+    #[async_std::test]
+    async fn requeues_dead_letters_of_purged_rows() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+        let event = dead_letter_purged_row(&mut conn).await;
+
+        assert!(outbox
+            .requeue_dead_letter(&mut conn, 1)
+            .await
+            .expect("Can't requeue dead letter"));
+        assert!(!outbox
+            .requeue_dead_letter(&mut conn, 1)
+            .await
+            .expect("Can't requeue dead letter"));
+
+        let row = outbox
+            .find_row(&mut conn, event.id)
+            .await
+            .expect("Can't find row")
+            .expect("Event is not requeued");
+        assert_eq!(row.topic, "user.created");
+        assert_eq!(row.attempts, 0);
+        assert_eq!(row.idempotency_key.as_deref(), Some("create-user"));
+        assert_eq!(row.event_type.as_deref(), Some("UserCreated"));
+        assert_eq!(count(&mut conn, "panacea_dead_letters").await, 0);
+    }
+
+    #[async_std::test]
+    async fn refuses_to_requeue_taken_idempotency_keys() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+        let event = dead_letter_purged_row(&mut conn).await;
+
+        // Another event with the same key is stored after the row has been purged
+        outbox
+            .store_event(
+                &mut conn,
+                Event {
+                    id: event::new_id(),
+                    ..event
+                },
+            )
+            .await
+            .expect("Can't store event");
+
+        assert!(matches!(
+            outbox.requeue_dead_letter(&mut conn, 1).await,
+            Err(Error::IdempotencyKeyTaken(key)) if key == "create-user"
+        ));
+        assert_eq!(count(&mut conn, "panacea_dead_letters").await, 1);
+    }
+
+    #[async_std::test]
+    async fn resets_dead_lettered_rows_on_requeue() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+
+        store(&mut conn, "user.created", Some("user"), "payload", None)
+            .await
+            .expect("Can't store event");
+        sqlx::query(
+            "UPDATE panacea_outbox SET attempts = 3, last_error = 'oops', dead_lettered_at = datetime('now')",
+        )
+        .execute(&mut conn)
+        .await
+        .expect("Can't dead-letter row");
+        outbox
+            .dead_letter_row(&mut conn, 1)
+            .await
+            .expect("Can't dead-letter row");
+
+        let dead_letters = outbox
+            .dead_letters(&mut conn, 10)
+            .await
+            .expect("Can't list dead letters");
+        assert_eq!(dead_letters[0].error, "oops");
+        assert_eq!(dead_letters[0].handler, None);
+
+        outbox
+            .requeue_dead_letter(&mut conn, dead_letters[0].sequence)
+            .await
+            .expect("Can't requeue dead letter");
+
+        let pending: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM panacea_outbox WHERE attempts = 0 AND dead_lettered_at IS NULL",
+        )
+        .fetch_one(&mut conn)
+        .await
+        .expect("Can't count rows");
+        assert_eq!(pending, 1);
+        assert_eq!(count(&mut conn, "panacea_outbox").await, 1);
+    }
+
+    #[async_std::test]
+    async fn discards_dead_letters() {
+        let mut conn = connect().await;
+        let outbox = Outbox::default();
+        let event = event::new(&"user.created", Some("user"), &"payload", None);
+
+        outbox
+            .store_dead_letter(&mut conn, &event, None, "oops", 1)
+            .await
+            .expect("Can't store dead letter");
+
+        assert!(outbox
+            .discard_dead_letter(&mut conn, 1)
+            .await
+            .expect("Can't discard dead letter"));
+        assert!(!outbox
+            .discard_dead_letter(&mut conn, 1)
+            .await
+            .expect("Can't discard dead letter"));
+        assert_eq!(count(&mut conn, "panacea_dead_letters").await, 0);
+    }
+}
@@ -0,0 +1,174 @@
+use super::{migrations::Migration, Outbox};
+
+/// SQL dialect of a database backend, the outbox can live in.
+///
+/// It's implemented for [`sqlx::MySql`], [`sqlx::Postgres`] and [`sqlx::Sqlite`] (with `mysql`,
+/// `postgres` and `sqlite` features respectively), so the outbox picks backend-specific SQL by
+/// the type of the connection, and several backends can be compiled together.
+pub trait Dialect: sqlx::Database {
+    /// Maximum number of bind parameters, that can be used in a single query.
+    const MAX_BIND_PARAMS: usize;
+    /// SQL expression for the current timestamp.
+    const NOW: &'static str;
+    /// Whether rows can be claimed with `FOR UPDATE SKIP LOCKED`. Rows are claimed with a lease
+    /// otherwise, see [`super::Relay::with_lease_duration`].
+    const SKIP_LOCKED: bool;
+    /// Whether index names are qualified with schema, rather than the indexed table.
+    const SCHEMA_QUALIFIED_INDEXES: bool;
+    /// Statement, that creates migrations table.
+    #[doc(hidden)]
+    const CREATE_MIGRATIONS_TABLE: &'static str;
+    /// Outbox schema migrations.
+    #[doc(hidden)]
+    const MIGRATIONS: &'static [Migration];
+
+    /// Returns bind parameter placeholder with given (1-based) index.
+    fn placeholder(index: usize) -> String;
+
+    /// Quotes SQL identifier.
+    fn quote(identifier: &str) -> String;
+
+    /// Returns number of rows, affected by a statement.
+    fn rows_affected(result: &<Self as sqlx::Database>::QueryResult) -> u64;
+
+    /// Returns SQL expression for the timestamp, that is given number of seconds (usually
+    /// a bind parameter) ago.
+    fn seconds_ago(seconds: &str) -> String;
+
+    /// Returns SQL expression, that converts JSON text (usually a bind parameter) to the type of
+    /// JSON columns, e.g. headers.
+    fn json(text: &str) -> String {
+        text.to_string()
+    }
+
+    /// Returns SQL expression, that converts JSON column to text.
+    fn json_text(column: &str) -> String {
+        column.to_string()
+    }
+
+    /// Rewrites `INSERT INTO ... VALUES ...` statement, so rows, that violate the unique
+    /// constraint of given (quoted) column, e.g. duplicate idempotency keys, are skipped instead
+    /// of failing the statement. Other errors, e.g. duplicate event ids, still fail it.
+    fn ignore_conflicts(insert: &str, column: &str) -> String;
+
+    /// Wraps `INSERT` statement, so it notifies listeners of the outbox about inserted rows.
+    /// Returns the statement as is, if backend has no notifications.
+    fn with_notification(_outbox: &Outbox, insert: String) -> String {
+        insert
+    }
+}
+
+#[cfg(feature = "mysql")]
+impl Dialect for sqlx::MySql {
+    const MAX_BIND_PARAMS: usize = 65_535;
+    const NOW: &'static str = "NOW()";
+    const SKIP_LOCKED: bool = true;
+    const SCHEMA_QUALIFIED_INDEXES: bool = false;
+    const CREATE_MIGRATIONS_TABLE: &'static str = super::migrations::MYSQL_CREATE_MIGRATIONS_TABLE;
+    const MIGRATIONS: &'static [Migration] = super::migrations::MYSQL_MIGRATIONS;
+
+    fn placeholder(_index: usize) -> String {
+        "?".to_string()
+    }
+
+    fn quote(identifier: &str) -> String {
+        format!("`{}`", identifier.replace('`', "``"))
+    }
+
+    fn rows_affected(result: &<Self as sqlx::Database>::QueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn seconds_ago(seconds: &str) -> String {
+        format!("NOW() - INTERVAL {seconds} SECOND")
+    }
+
+    /// MySQL can't target a single unique constraint, so conflicts with any of them (e.g.
+    /// duplicate event ids as well) are skipped. Unlike `INSERT IGNORE`, it doesn't downgrade
+    /// other errors (e.g. truncated values) to warnings.
+    fn ignore_conflicts(insert: &str, column: &str) -> String {
+        format!(
+            "{} ON DUPLICATE KEY UPDATE {column} = {column}",
+            insert.trim_end()
+        )
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl Dialect for sqlx::Postgres {
+    const MAX_BIND_PARAMS: usize = 65_535;
+    const NOW: &'static str = "NOW()";
+    const SKIP_LOCKED: bool = true;
+    const SCHEMA_QUALIFIED_INDEXES: bool = false;
+    const CREATE_MIGRATIONS_TABLE: &'static str =
+        super::migrations::POSTGRES_CREATE_MIGRATIONS_TABLE;
+    const MIGRATIONS: &'static [Migration] = super::migrations::POSTGRES_MIGRATIONS;
+
+    fn placeholder(index: usize) -> String {
+        format!("${index}")
+    }
+
+    fn quote(identifier: &str) -> String {
+        format!("\"{}\"", identifier.replace('"', "\"\""))
+    }
+
+    fn rows_affected(result: &<Self as sqlx::Database>::QueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn seconds_ago(seconds: &str) -> String {
+        format!("NOW() - {seconds} * INTERVAL '1 second'")
+    }
+
+    fn json(text: &str) -> String {
+        format!("CAST({text} AS JSONB)")
+    }
+
+    fn json_text(column: &str) -> String {
+        format!("CAST({column} AS TEXT)")
+    }
+
+    fn ignore_conflicts(insert: &str, column: &str) -> String {
+        format!("{} ON CONFLICT ({column}) DO NOTHING", insert.trim_end())
+    }
+
+    fn with_notification(outbox: &Outbox, insert: String) -> String {
+        let channel = outbox.notify_channel().replace('\'', "''");
+        let sequence = outbox.sql::<Self>("{sequence}");
+
+        format!(
+            "WITH inserted AS ({insert} RETURNING {sequence}) \
+             SELECT pg_notify('{channel}', '') FROM inserted LIMIT 1"
+        )
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Dialect for sqlx::Sqlite {
+    const MAX_BIND_PARAMS: usize = 32_766;
+    const NOW: &'static str = "datetime('now')";
+    const SKIP_LOCKED: bool = false;
+    const SCHEMA_QUALIFIED_INDEXES: bool = true;
+    const CREATE_MIGRATIONS_TABLE: &'static str = super::migrations::SQLITE_CREATE_MIGRATIONS_TABLE;
+    const MIGRATIONS: &'static [Migration] = super::migrations::SQLITE_MIGRATIONS;
+
+    fn placeholder(_index: usize) -> String {
+        "?".to_string()
+    }
+
+    fn quote(identifier: &str) -> String {
+        format!("\"{}\"", identifier.replace('"', "\"\""))
+    }
+
+    fn rows_affected(result: &<Self as sqlx::Database>::QueryResult) -> u64 {
+        result.rows_affected()
+    }
+
+    fn seconds_ago(seconds: &str) -> String {
+        format!("datetime('now', '-' || {seconds} || ' seconds')")
+    }
+
+    fn ignore_conflicts(insert: &str, column: &str) -> String {
+        format!("{} ON CONFLICT ({column}) DO NOTHING", insert.trim_end())
+    }
+}
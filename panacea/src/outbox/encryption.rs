@@ -0,0 +1,292 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+
+use uuid::Uuid;
+
+use panacea_types::event::{Headers, ENCRYPTION_DATA_KEY_HEADER, ENCRYPTION_KEY_ID_HEADER};
+
+/// 256-bit key, that wraps data keys of event payloads.
+pub type Key = [u8; 32];
+
+/// Size of the nonce, prepended to sealed data.
+const NONCE_SIZE: usize = 24;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown encryption key `{0}`")]
+    UnknownKey(String),
+    #[error("missing or malformed data key")]
+    MalformedDataKey,
+    #[error("can't encrypt payload")]
+    Encryption,
+    #[error("can't decrypt payload")]
+    Decryption,
+}
+
+/// Source of keys, that wrap data keys of encrypted events.
+///
+/// Keys are looked up by their ids, recorded in the [`ENCRYPTION_KEY_ID_HEADER`] of encrypted
+/// events. To rotate keys, switch the current key id to a new key, and keep providing retired
+/// keys, while there are rows encrypted with them.
+pub trait KeyProvider: Send + Sync {
+    /// Returns id of the key, new events are encrypted with.
+    fn current_key_id(&self) -> String;
+
+    /// Returns key with given id, if it's known.
+    fn key(&self, key_id: &str) -> Option<Key>;
+}
+
+/// [`KeyProvider`] with a fixed set of keys.
+#[derive(Clone)]
+pub struct StaticKeys {
+    current_key_id: String,
+    keys: HashMap<String, Key>,
+}
+
+impl StaticKeys {
+    /// Constructs provider, that encrypts new events with given key.
+    #[must_use]
+    pub fn new(key_id: impl Into<String>, key: Key) -> Self {
+        let key_id = key_id.into();
+
+        Self {
+            current_key_id: key_id.clone(),
+            keys: HashMap::from([(key_id, key)]),
+        }
+    }
+
+    /// Adds retired key, that is only used to decrypt events, encrypted before rotation.
+    #[must_use]
+    pub fn with_key(mut self, key_id: impl Into<String>, key: Key) -> Self {
+        self.keys.insert(key_id.into(), key);
+
+        self
+    }
+}
+
+impl KeyProvider for StaticKeys {
+    fn current_key_id(&self) -> String {
+        self.current_key_id.clone()
+    }
+
+    fn key(&self, key_id: &str) -> Option<Key> {
+        self.keys.get(key_id).copied()
+    }
+}
+
+impl fmt::Debug for StaticKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticKeys")
+            .field("current_key_id", &self.current_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Envelope encryption of stored event payloads with XChaCha20-Poly1305.
+///
+/// Every payload is encrypted with its own random data key. The data key is wrapped with the
+/// current key of the [`KeyProvider`], and stored in the [`ENCRYPTION_DATA_KEY_HEADER`] along
+/// with id of the key in the [`ENCRYPTION_KEY_ID_HEADER`], so keys can be rotated without
+/// re-encrypting stored rows.
+///
+/// Both the payload and the data key are bound to the event id and the key id (as associated
+/// data), so encrypted payloads and their headers can't be moved to other rows or relabeled with
+/// other keys.
+#[derive(Clone)]
+pub struct Encryption {
+    keys: Arc<dyn KeyProvider>,
+}
+
+impl Encryption {
+    #[must_use]
+    pub fn new(keys: impl KeyProvider + 'static) -> Self {
+        Self {
+            keys: Arc::new(keys),
+        }
+    }
+
+    /// Encrypts payload of the event with given id, recording key id and wrapped data key in the
+    /// headers.
+    pub(crate) fn encrypt(
+        &self,
+        id: Uuid,
+        payload: &[u8],
+        headers: &mut Headers,
+    ) -> Result<Vec<u8>, Error> {
+        let key_id = self.keys.current_key_id();
+        let key = self
+            .keys
+            .key(&key_id)
+            .ok_or_else(|| Error::UnknownKey(key_id.clone()))?;
+
+        let aad = associated_data(id, &key_id);
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let payload = seal(&data_key, payload, &aad)?;
+        let wrapped_data_key = seal(&key.into(), &data_key, &aad)?;
+
+        headers.insert(ENCRYPTION_KEY_ID_HEADER.to_string(), key_id);
+        headers.insert(
+            ENCRYPTION_DATA_KEY_HEADER.to_string(),
+            BASE64.encode(wrapped_data_key),
+        );
+
+        Ok(payload)
+    }
+
+    /// Decrypts payload of the event with given id, removing encryption headers.
+    pub(crate) fn decrypt(
+        &self,
+        id: Uuid,
+        payload: &[u8],
+        headers: &mut Headers,
+    ) -> Result<Vec<u8>, Error> {
+        let key_id = headers.remove(ENCRYPTION_KEY_ID_HEADER).unwrap_or_default();
+        let wrapped_data_key = headers
+            .remove(ENCRYPTION_DATA_KEY_HEADER)
+            .and_then(|data_key| BASE64.decode(data_key).ok())
+            .ok_or(Error::MalformedDataKey)?;
+        let key = self
+            .keys
+            .key(&key_id)
+            .ok_or_else(|| Error::UnknownKey(key_id.clone()))?;
+
+        let aad = associated_data(id, &key_id);
+        let data_key = open(&key.into(), &wrapped_data_key, &aad)?;
+        if data_key.len() != 32 {
+            return Err(Error::MalformedDataKey);
+        }
+
+        open(chacha20poly1305::Key::from_slice(&data_key), payload, &aad)
+    }
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Encryption {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::as_ptr(&self.keys).cast::<()>() == Arc::as_ptr(&other.keys).cast::<()>()
+    }
+}
+
+impl Eq for Encryption {}
+
+/// Returns associated data, that binds encrypted payload to the event and the key: the event id
+/// followed by the key id.
+fn associated_data(id: Uuid, key_id: &str) -> Vec<u8> {
+    [id.as_bytes().as_slice(), key_id.as_bytes()].concat()
+}
+
+/// Encrypts data with random nonce, prepended to the result.
+fn seal(key: &chacha20poly1305::Key, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let encrypted = XChaCha20Poly1305::new(key)
+        .encrypt(&nonce, Payload { msg: data, aad })
+        .map_err(|_| Error::Encryption)?;
+
+    Ok([nonce.as_slice(), &encrypted].concat())
+}
+
+/// Decrypts data, sealed by [`seal`].
+fn open(key: &chacha20poly1305::Key, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    if sealed.len() < NONCE_SIZE {
+        return Err(Error::Decryption);
+    }
+    let (nonce, encrypted) = sealed.split_at(NONCE_SIZE);
+
+    XChaCha20Poly1305::new(key)
+        .decrypt(
+            XNonce::from_slice(nonce),
+            Payload {
+                msg: encrypted,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Decryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decrypts_payloads_after_key_rotation() {
+        let id = Uuid::now_v7();
+        let mut headers = Headers::new();
+        let encrypted = Encryption::new(StaticKeys::new("2023-01", [1; 32]))
+            .encrypt(id, b"secret", &mut headers)
+            .expect("Can't encrypt");
+
+        assert_ne!(encrypted, b"secret");
+        assert_eq!(
+            headers.get(ENCRYPTION_KEY_ID_HEADER),
+            Some(&"2023-01".to_string())
+        );
+
+        let rotated =
+            Encryption::new(StaticKeys::new("2023-02", [2; 32]).with_key("2023-01", [1; 32]));
+        let decrypted = rotated
+            .decrypt(id, &encrypted, &mut headers)
+            .expect("Can't decrypt");
+
+        assert_eq!(decrypted, b"secret");
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn refuses_unknown_keys_and_tampered_payloads() {
+        let id = Uuid::now_v7();
+        let encryption = Encryption::new(StaticKeys::new("2023-01", [1; 32]));
+        let mut headers = Headers::new();
+        let mut encrypted = encryption
+            .encrypt(id, b"secret", &mut headers)
+            .expect("Can't encrypt");
+
+        assert!(matches!(
+            Encryption::new(StaticKeys::new("2023-02", [2; 32])).decrypt(
+                id,
+                &encrypted,
+                &mut headers.clone()
+            ),
+            Err(Error::UnknownKey(_))
+        ));
+
+        encrypted[NONCE_SIZE] ^= 1;
+        assert!(matches!(
+            encryption.decrypt(id, &encrypted, &mut headers),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn binds_payloads_to_event_and_key() {
+        let id = Uuid::now_v7();
+        let keys = StaticKeys::new("2023-01", [1; 32]).with_key("2023-02", [1; 32]);
+        let encryption = Encryption::new(keys);
+        let mut headers = Headers::new();
+        let encrypted = encryption
+            .encrypt(id, b"secret", &mut headers)
+            .expect("Can't encrypt");
+
+        // Payload of another event
+        assert!(matches!(
+            encryption.decrypt(Uuid::now_v7(), &encrypted, &mut headers.clone()),
+            Err(Error::Decryption)
+        ));
+
+        // Relabeled with another key id, even if the key is the same
+        headers.insert(ENCRYPTION_KEY_ID_HEADER.to_string(), "2023-02".to_string());
+        assert!(matches!(
+            encryption.decrypt(id, &encrypted, &mut headers),
+            Err(Error::Decryption)
+        ));
+    }
+}
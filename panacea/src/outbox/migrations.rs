@@ -0,0 +1,652 @@
+use sqlx::{
+    database::HasArguments, Acquire, Connection, Decode, Encode, Executor, IntoArguments, Type,
+};
+
+use super::{Dialect, Error, Outbox};
+
+/// Single step of the outbox schema evolution.
+pub struct Migration {
+    /// Schema version, this migration upgrades to.
+    version: i64,
+    /// Human readable description, stored along with the version.
+    description: &'static str,
+    /// SQL statements, executed in order.
+    statements: &'static [&'static str],
+}
+
+#[cfg(feature = "mysql")]
+pub(super) const MYSQL_CREATE_MIGRATIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS {migrations} (
+        version BIGINT NOT NULL PRIMARY KEY,
+        description VARCHAR(255) NOT NULL,
+        applied_at TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+    )
+"#;
+
+#[cfg(feature = "postgres")]
+pub(super) const POSTGRES_CREATE_MIGRATIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS {migrations} (
+        version BIGINT NOT NULL PRIMARY KEY,
+        description TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    )
+"#;
+
+#[cfg(feature = "sqlite")]
+pub(super) const SQLITE_CREATE_MIGRATIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS {migrations} (
+        version INTEGER NOT NULL PRIMARY KEY,
+        description TEXT NOT NULL,
+        applied_at TEXT NOT NULL DEFAULT (datetime('now'))
+    )
+"#;
+
+const INSERT_MIGRATION: &str = "INSERT INTO {migrations} (version, description) VALUES ($1, $2)";
+
+#[cfg(feature = "mysql")]
+pub(super) const MYSQL_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create outbox table",
+        statements: &[r#"
+            CREATE TABLE IF NOT EXISTS {outbox} (
+                {sequence} BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+                {topic} VARCHAR(255) NOT NULL,
+                {key} VARCHAR(255),
+                {payload} LONGBLOB NOT NULL,
+                {headers} MEDIUMTEXT NOT NULL,
+                {created_at} TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+                {published_at} TIMESTAMP(6) NULL,
+                INDEX {unpublished_idx} ({published_at}, {sequence})
+            )
+        "#],
+    },
+    Migration {
+        version: 2,
+        description: "add event id",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {id} BINARY(16) NULL AFTER {sequence}",
+            "UPDATE {outbox} SET {id} = UNHEX(REPLACE(UUID(), '-', '')) WHERE {id} IS NULL",
+            r#"
+                ALTER TABLE {outbox}
+                MODIFY {id} BINARY(16) NOT NULL,
+                ADD UNIQUE INDEX {id_idx} ({id})
+            "#,
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "add outbox archive",
+        statements: &[r#"
+            CREATE TABLE IF NOT EXISTS {archive} (
+                {sequence} BIGINT NOT NULL PRIMARY KEY,
+                {id} BINARY(16) NOT NULL,
+                {topic} VARCHAR(255) NOT NULL,
+                {key} VARCHAR(255),
+                {payload} LONGBLOB NOT NULL,
+                {headers} MEDIUMTEXT NOT NULL,
+                {created_at} TIMESTAMP(6) NOT NULL,
+                {published_at} TIMESTAMP(6) NULL,
+                archived_at TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6),
+                UNIQUE INDEX {archive_id_idx} ({id})
+            )
+        "#],
+    },
+    Migration {
+        version: 4,
+        description: "add scheduled delivery",
+        statements: &["ALTER TABLE {outbox} ADD COLUMN {deliver_at} TIMESTAMP(6) NULL"],
+    },
+    Migration {
+        version: 5,
+        description: "add event expiry",
+        statements: &[
+            r#"
+                ALTER TABLE {outbox}
+                ADD COLUMN {expires_at} TIMESTAMP(6) NULL,
+                ADD COLUMN {expired_at} TIMESTAMP(6) NULL
+            "#,
+            "ALTER TABLE {archive} ADD COLUMN {expired_at} TIMESTAMP(6) NULL",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "add publish attempts",
+        statements: &[r#"
+            ALTER TABLE {outbox}
+            ADD COLUMN {attempts} INT NOT NULL DEFAULT 0,
+            ADD COLUMN {last_error} TEXT NULL,
+            ADD COLUMN {next_attempt_at} TIMESTAMP(6) NULL,
+            ADD COLUMN {dead_lettered_at} TIMESTAMP(6) NULL
+        "#],
+    },
+    Migration {
+        version: 7,
+        description: "add dead letters",
+        statements: &[r#"
+            CREATE TABLE IF NOT EXISTS {dead_letters} (
+                {sequence} BIGINT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+                {id} BINARY(16) NOT NULL,
+                {topic} VARCHAR(255) NOT NULL,
+                {key} VARCHAR(255),
+                {payload} LONGBLOB NOT NULL,
+                {headers} MEDIUMTEXT NOT NULL,
+                {created_at} TIMESTAMP(6) NOT NULL,
+                {attempts} INT NOT NULL,
+                {last_error} TEXT NOT NULL,
+                handler VARCHAR(255) NULL,
+                {dead_lettered_at} TIMESTAMP(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6)
+            )
+        "#],
+    },
+    Migration {
+        version: 8,
+        description: "add idempotency keys",
+        statements: &[r#"
+            ALTER TABLE {outbox}
+            ADD COLUMN {idempotency_key} VARCHAR(255) NULL,
+            ADD UNIQUE INDEX {idempotency_key_idx} ({idempotency_key})
+        "#],
+    },
+    Migration {
+        version: 9,
+        description: "add event type",
+        statements: &["ALTER TABLE {outbox} ADD COLUMN {event_type} VARCHAR(255) NULL"],
+    },
+    Migration {
+        version: 10,
+        description: "add event columns to dead letters",
+        statements: &[r#"
+            ALTER TABLE {dead_letters}
+            ADD COLUMN {deliver_at} TIMESTAMP(6) NULL,
+            ADD COLUMN {expires_at} TIMESTAMP(6) NULL,
+            ADD COLUMN {idempotency_key} VARCHAR(255) NULL,
+            ADD COLUMN {event_type} VARCHAR(255) NULL
+        "#],
+    },
+    Migration {
+        version: 11,
+        description: "add event columns to archive",
+        statements: &[r#"
+            ALTER TABLE {archive}
+            ADD COLUMN {deliver_at} TIMESTAMP(6) NULL,
+            ADD COLUMN {expires_at} TIMESTAMP(6) NULL,
+            ADD COLUMN {attempts} INT NOT NULL DEFAULT 0,
+            ADD COLUMN {last_error} TEXT NULL,
+            ADD COLUMN {dead_lettered_at} TIMESTAMP(6) NULL,
+            ADD COLUMN {idempotency_key} VARCHAR(255) NULL,
+            ADD COLUMN {event_type} VARCHAR(255) NULL
+        "#],
+    },
+    Migration {
+        version: 12,
+        description: "add dead letter origin",
+        statements: &[
+            "ALTER TABLE {dead_letters} ADD COLUMN origin VARCHAR(16) NOT NULL DEFAULT 'relay'",
+            "UPDATE {dead_letters} SET origin = 'worker' WHERE handler IS NOT NULL",
+        ],
+    },
+];
+
+#[cfg(feature = "postgres")]
+pub(super) const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create outbox table",
+        statements: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS {outbox} (
+                {sequence} BIGSERIAL PRIMARY KEY,
+                {topic} TEXT NOT NULL,
+                {key} TEXT,
+                {payload} BYTEA NOT NULL,
+                {headers} TEXT NOT NULL,
+                {created_at} TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                {published_at} TIMESTAMPTZ
+            )
+        "#,
+            r#"
+            CREATE INDEX IF NOT EXISTS {unpublished_idx}
+            ON {outbox} ({sequence}) WHERE {published_at} IS NULL
+        "#,
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "add event id",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {id} UUID",
+            "UPDATE {outbox} SET {id} = gen_random_uuid() WHERE {id} IS NULL",
+            "ALTER TABLE {outbox} ALTER COLUMN {id} SET NOT NULL",
+            "CREATE UNIQUE INDEX IF NOT EXISTS {id_idx} ON {outbox} ({id})",
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "add outbox archive",
+        statements: &[
+            r#"
+                CREATE INDEX IF NOT EXISTS {published_idx}
+                ON {outbox} ({published_at}) WHERE {published_at} IS NOT NULL
+            "#,
+            r#"
+                CREATE TABLE IF NOT EXISTS {archive} (
+                    {sequence} BIGINT NOT NULL PRIMARY KEY,
+                    {id} UUID NOT NULL UNIQUE,
+                    {topic} TEXT NOT NULL,
+                    {key} TEXT,
+                    {payload} BYTEA NOT NULL,
+                    {headers} TEXT NOT NULL,
+                    {created_at} TIMESTAMPTZ NOT NULL,
+                    {published_at} TIMESTAMPTZ,
+                    archived_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )
+            "#,
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "store headers as jsonb",
+        statements: &[
+            "ALTER TABLE {outbox} ALTER COLUMN {headers} TYPE JSONB USING {headers}::JSONB",
+            "ALTER TABLE {archive} ALTER COLUMN {headers} TYPE JSONB USING {headers}::JSONB",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "add scheduled delivery",
+        statements: &["ALTER TABLE {outbox} ADD COLUMN {deliver_at} TIMESTAMPTZ"],
+    },
+    Migration {
+        version: 6,
+        description: "add event expiry",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {expires_at} TIMESTAMPTZ",
+            "ALTER TABLE {outbox} ADD COLUMN {expired_at} TIMESTAMPTZ",
+            "ALTER TABLE {archive} ADD COLUMN {expired_at} TIMESTAMPTZ",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "add publish attempts",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {attempts} INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE {outbox} ADD COLUMN {last_error} TEXT",
+            "ALTER TABLE {outbox} ADD COLUMN {next_attempt_at} TIMESTAMPTZ",
+            "ALTER TABLE {outbox} ADD COLUMN {dead_lettered_at} TIMESTAMPTZ",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "add dead letters",
+        statements: &[r#"
+            CREATE TABLE IF NOT EXISTS {dead_letters} (
+                {sequence} BIGSERIAL PRIMARY KEY,
+                {id} UUID NOT NULL,
+                {topic} TEXT NOT NULL,
+                {key} TEXT,
+                {payload} BYTEA NOT NULL,
+                {headers} JSONB NOT NULL,
+                {created_at} TIMESTAMPTZ NOT NULL,
+                {attempts} INTEGER NOT NULL,
+                {last_error} TEXT NOT NULL,
+                handler TEXT,
+                {dead_lettered_at} TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            )
+        "#],
+    },
+    Migration {
+        version: 9,
+        description: "add idempotency keys",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {idempotency_key} TEXT",
+            r#"
+                CREATE UNIQUE INDEX IF NOT EXISTS {idempotency_key_idx}
+                ON {outbox} ({idempotency_key})
+            "#,
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "add event type",
+        statements: &["ALTER TABLE {outbox} ADD COLUMN {event_type} TEXT"],
+    },
+    Migration {
+        version: 11,
+        description: "add event columns to dead letters",
+        statements: &[
+            "ALTER TABLE {dead_letters} ADD COLUMN {deliver_at} TIMESTAMPTZ",
+            "ALTER TABLE {dead_letters} ADD COLUMN {expires_at} TIMESTAMPTZ",
+            "ALTER TABLE {dead_letters} ADD COLUMN {idempotency_key} TEXT",
+            "ALTER TABLE {dead_letters} ADD COLUMN {event_type} TEXT",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "add event columns to archive",
+        statements: &[
+            "ALTER TABLE {archive} ADD COLUMN {deliver_at} TIMESTAMPTZ",
+            "ALTER TABLE {archive} ADD COLUMN {expires_at} TIMESTAMPTZ",
+            "ALTER TABLE {archive} ADD COLUMN {attempts} INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE {archive} ADD COLUMN {last_error} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {dead_lettered_at} TIMESTAMPTZ",
+            "ALTER TABLE {archive} ADD COLUMN {idempotency_key} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {event_type} TEXT",
+        ],
+    },
+    Migration {
+        version: 13,
+        description: "add dead letter origin",
+        statements: &[
+            "ALTER TABLE {dead_letters} ADD COLUMN origin TEXT NOT NULL DEFAULT 'relay'",
+            "UPDATE {dead_letters} SET origin = 'worker' WHERE handler IS NOT NULL",
+        ],
+    },
+];
+
+#[cfg(feature = "sqlite")]
+pub(super) const SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create outbox table",
+        statements: &[
+            r#"
+            CREATE TABLE IF NOT EXISTS {outbox} (
+                {sequence} INTEGER PRIMARY KEY AUTOINCREMENT,
+                {topic} TEXT NOT NULL,
+                {key} TEXT,
+                {payload} BLOB NOT NULL,
+                {headers} TEXT NOT NULL,
+                {created_at} TEXT NOT NULL DEFAULT (datetime('now')),
+                {published_at} TEXT
+            )
+        "#,
+            r#"
+            CREATE INDEX IF NOT EXISTS {unpublished_idx}
+            ON {outbox_name} ({sequence}) WHERE {published_at} IS NULL
+        "#,
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "add event id",
+        statements: &[
+            // SQLite can't add `NOT NULL` columns without default value, so the column stays
+            // nullable, but every row gets an id.
+            "ALTER TABLE {outbox} ADD COLUMN {id} BLOB",
+            "UPDATE {outbox} SET {id} = randomblob(16) WHERE {id} IS NULL",
+            "CREATE UNIQUE INDEX IF NOT EXISTS {id_idx} ON {outbox_name} ({id})",
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "add relay leases",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {claimed_by} TEXT",
+            "ALTER TABLE {outbox} ADD COLUMN {claimed_until} TEXT",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "add outbox archive",
+        statements: &[
+            r#"
+                CREATE INDEX IF NOT EXISTS {published_idx}
+                ON {outbox_name} ({published_at}) WHERE {published_at} IS NOT NULL
+            "#,
+            r#"
+                CREATE TABLE IF NOT EXISTS {archive} (
+                    {sequence} INTEGER NOT NULL PRIMARY KEY,
+                    {id} BLOB NOT NULL UNIQUE,
+                    {topic} TEXT NOT NULL,
+                    {key} TEXT,
+                    {payload} BLOB NOT NULL,
+                    {headers} TEXT NOT NULL,
+                    {created_at} TEXT NOT NULL,
+                    {published_at} TEXT,
+                    archived_at TEXT NOT NULL DEFAULT (datetime('now'))
+                )
+            "#,
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "add scheduled delivery",
+        statements: &["ALTER TABLE {outbox} ADD COLUMN {deliver_at} TEXT"],
+    },
+    Migration {
+        version: 6,
+        description: "add event expiry",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {expires_at} TEXT",
+            "ALTER TABLE {outbox} ADD COLUMN {expired_at} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {expired_at} TEXT",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "add publish attempts",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {attempts} INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE {outbox} ADD COLUMN {last_error} TEXT",
+            "ALTER TABLE {outbox} ADD COLUMN {next_attempt_at} TEXT",
+            "ALTER TABLE {outbox} ADD COLUMN {dead_lettered_at} TEXT",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "add dead letters",
+        statements: &[r#"
+            CREATE TABLE IF NOT EXISTS {dead_letters} (
+                {sequence} INTEGER PRIMARY KEY AUTOINCREMENT,
+                {id} BLOB NOT NULL,
+                {topic} TEXT NOT NULL,
+                {key} TEXT,
+                {payload} BLOB NOT NULL,
+                {headers} TEXT NOT NULL,
+                {created_at} TEXT NOT NULL,
+                {attempts} INTEGER NOT NULL,
+                {last_error} TEXT NOT NULL,
+                handler TEXT,
+                {dead_lettered_at} TEXT NOT NULL DEFAULT (datetime('now'))
+            )
+        "#],
+    },
+    Migration {
+        version: 9,
+        description: "add idempotency keys",
+        statements: &[
+            "ALTER TABLE {outbox} ADD COLUMN {idempotency_key} TEXT",
+            r#"
+                CREATE UNIQUE INDEX IF NOT EXISTS {idempotency_key_idx}
+                ON {outbox_name} ({idempotency_key})
+            "#,
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "add event type",
+        statements: &["ALTER TABLE {outbox} ADD COLUMN {event_type} TEXT"],
+    },
+    Migration {
+        version: 11,
+        description: "add event columns to dead letters",
+        statements: &[
+            "ALTER TABLE {dead_letters} ADD COLUMN {deliver_at} TEXT",
+            "ALTER TABLE {dead_letters} ADD COLUMN {expires_at} TEXT",
+            "ALTER TABLE {dead_letters} ADD COLUMN {idempotency_key} TEXT",
+            "ALTER TABLE {dead_letters} ADD COLUMN {event_type} TEXT",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "add event columns to archive",
+        statements: &[
+            "ALTER TABLE {archive} ADD COLUMN {deliver_at} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {expires_at} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {attempts} INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE {archive} ADD COLUMN {last_error} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {dead_lettered_at} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {idempotency_key} TEXT",
+            "ALTER TABLE {archive} ADD COLUMN {event_type} TEXT",
+        ],
+    },
+    Migration {
+        version: 13,
+        description: "add dead letter origin",
+        statements: &[
+            "ALTER TABLE {dead_letters} ADD COLUMN origin TEXT NOT NULL DEFAULT 'relay'",
+            "UPDATE {dead_letters} SET origin = 'worker' WHERE handler IS NOT NULL",
+        ],
+    },
+];
+
+impl Outbox {
+    /// Creates or upgrades the outbox table and its indexes to the latest schema version,
+    /// supported by this version of `panacea`.
+    ///
+    /// Applied versions are recorded in the migrations table of this outbox (e.g.
+    /// `panacea_outbox_migrations`), so calling this method on an up-to-date database is a
+    /// no-op. Every version is applied in its own transaction (note that MySQL implicitly
+    /// commits DDL statements, so a failed migration may leave the schema partially upgraded
+    /// there).
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if there is any error occurs when migrating the database, or if
+    /// the database schema is newer than this version of `panacea` supports.
+    pub async fn migrate<'a, A, DB>(&self, executor: A) -> Result<(), Error>
+    where
+        A: Acquire<'a, Database = DB>,
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> i64: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        for<'q> &'q str: Type<DB> + Encode<'q, DB>,
+        usize: sqlx::ColumnIndex<<DB as sqlx::database::Database>::Row>,
+    {
+        let mut conn = executor.acquire().await?;
+
+        sqlx::query(&self.sql::<DB>(DB::CREATE_MIGRATIONS_TABLE))
+            .execute(&mut *conn)
+            .await?;
+
+        let current: Option<i64> =
+            sqlx::query_scalar(&self.sql::<DB>("SELECT MAX(version) FROM {migrations}"))
+                .fetch_one(&mut *conn)
+                .await?;
+        let current = current.unwrap_or_default();
+
+        if current > latest_version::<DB>() {
+            return Err(Error::UnsupportedSchemaVersion(current));
+        }
+
+        let insert_migration = self.sql::<DB>(INSERT_MIGRATION);
+
+        for migration in DB::MIGRATIONS.iter().filter(|m| m.version > current) {
+            let mut tx = conn.begin().await?;
+
+            for statement in migration.statements {
+                sqlx::query(&self.sql::<DB>(statement))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+
+            sqlx::query(&insert_migration)
+                .bind(migration.version)
+                .bind(migration.description)
+                .execute(&mut *tx)
+                .await?;
+
+            tx.commit().await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Migrates the default outbox table. See [`Outbox::migrate`].
+///
+/// # Errors
+///
+/// Will return an [`Error`] if there is any error occurs when migrating the database, or if the
+/// database schema is newer than this version of `panacea` supports.
+pub async fn migrate<'a, A, DB>(executor: A) -> Result<(), Error>
+where
+    A: Acquire<'a, Database = DB>,
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> i64: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    for<'q> &'q str: Type<DB> + Encode<'q, DB>,
+    usize: sqlx::ColumnIndex<<DB as sqlx::database::Database>::Row>,
+{
+    Outbox::default().migrate(executor).await
+}
+
+/// Returns the latest outbox schema version on `DB`, supported by this version of `panacea`.
+#[must_use]
+pub fn latest_version<DB: Dialect>() -> i64 {
+    DB::MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Connection, Sqlite, SqliteConnection};
+
+    use super::*;
+
+    async fn connect() -> SqliteConnection {
+        SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite")
+    }
+
+    async fn applied_versions(conn: &mut SqliteConnection) -> Vec<i64> {
+        sqlx::query_scalar("SELECT version FROM panacea_outbox_migrations ORDER BY version")
+            .fetch_all(conn)
+            .await
+            .expect("Can't fetch applied versions")
+    }
+
+    #[async_std::test]
+    async fn applies_all_migrations() {
+        let mut conn = connect().await;
+
+        migrate(&mut conn).await.expect("Can't migrate");
+
+        let expected: Vec<i64> = Sqlite::MIGRATIONS.iter().map(|m| m.version).collect();
+        assert_eq!(applied_versions(&mut conn).await, expected);
+    }
+
+    #[async_std::test]
+    async fn is_idempotent() {
+        let mut conn = connect().await;
+
+        migrate(&mut conn).await.expect("Can't migrate");
+        migrate(&mut conn).await.expect("Can't migrate again");
+
+        assert_eq!(
+            applied_versions(&mut conn).await.len(),
+            Sqlite::MIGRATIONS.len()
+        );
+    }
+
+    #[async_std::test]
+    async fn refuses_newer_schema() {
+        let mut conn = connect().await;
+        migrate(&mut conn).await.expect("Can't migrate");
+
+        sqlx::query(&Outbox::default().sql::<Sqlite>(INSERT_MIGRATION))
+            .bind(latest_version::<Sqlite>() + 1)
+            .bind("from the future")
+            .execute(&mut conn)
+            .await
+            .expect("Can't insert migration");
+
+        assert!(matches!(
+            migrate(&mut conn).await,
+            Err(Error::UnsupportedSchemaVersion(_))
+        ));
+    }
+}
@@ -11,18 +11,48 @@ compile_error!(
     "you should enable one of the `mysql`, `postgres` or `sqlite` features of `panacea`"
 );
 
-// Fail if multiple of `mysql`, `postgres` or `sqlite` features are enabled.
-#[cfg(all(feature = "mysql", feature = "postgres"))]
-compile_error!("you can't enable both `mysql` and `postgres` features of `panacea`");
-#[cfg(all(feature = "mysql", feature = "sqlite"))]
-compile_error!("you can't enable both `mysql` and `sqlite` features of `panacea`");
-#[cfg(all(feature = "postgres", feature = "sqlite"))]
-compile_error!("you can't enable both `postgres` and `sqlite` features of `panacea`");
+mod admin;
+mod backoff;
+mod compression;
+mod config;
+mod dead_letters;
+mod dialect;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod migrations;
+mod relay;
+mod retention;
+mod stream;
+mod transaction;
+
+pub use admin::Counts;
+pub use backoff::Backoff;
+pub use compression::{Compression, Encoding};
+pub use config::{Columns, Outbox};
+pub(crate) use dead_letters::error_chain;
+pub use dead_letters::{DeadLetter, DeadLetterOrigin};
+pub use dialect::Dialect;
+#[cfg(feature = "encryption")]
+pub use encryption::{Encryption, Key, KeyProvider, StaticKeys};
+pub use migrations::{latest_version, migrate};
+pub use relay::{OnPublished, Relay};
+pub use retention::{Retention, RetentionAction};
+pub use stream::{follow, stream};
+pub use transaction::OutboxTransaction;
 
 use chrono::{DateTime, Utc};
-use sqlx::{database::HasArguments, Encode, Executor, IntoArguments, Type};
+use sqlx::{
+    database::HasArguments, Acquire, ColumnIndex, Decode, Encode, Executor, IntoArguments, Type,
+};
+use uuid::Uuid;
+
+use serde::Serialize;
 
-use panacea_types::event::{self, AsBytesRef, Event, Headers};
+use panacea_types::{
+    codec::{self, Codec},
+    event::{self, AsBytesRef, Event, Headers},
+    publisher,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -30,125 +60,775 @@ pub enum Error {
     Database(#[from] sqlx::Error),
     #[error("can't encode headers")]
     HeadersEncoding(serde_json::Error),
+    #[error("can't encode payload")]
+    PayloadEncoding(codec::Error),
+    #[error("can't compress payload")]
+    Compression(std::io::Error),
+    #[cfg(feature = "encryption")]
+    #[error("can't encrypt payload")]
+    Encryption(encryption::Error),
+    #[error("can't decode event")]
+    EventDecoding(#[source] event::Error),
+    #[error("can't publish event")]
+    Publishing(#[source] publisher::Error),
+    #[error("outbox schema version {0} is not supported by this version of panacea")]
+    UnsupportedSchemaVersion(i64),
+    #[error("dead letter #{0} holds a consumed event, that can't be requeued to the outbox")]
+    NotRequeueable(i64),
+    #[error("idempotency key `{0}` is taken by another event of the outbox")]
+    IdempotencyKeyTaken(String),
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct EventRow {
+    pub sequence: i64,
+    pub id: Uuid,
     pub topic: String,
     pub key: Option<String>,
     pub payload: Vec<u8>,
     pub headers: String,
     pub created_at: DateTime<Utc>,
+    pub deliver_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Number of failed attempts to publish the event.
+    pub attempts: i32,
+    pub idempotency_key: Option<String>,
+    /// Value of the event type column, that carries the [`event::EVENT_TYPE_HEADER`].
+    pub event_type: Option<String>,
 }
 
-/// Constructs [`Event`] and stores it to outbox table.
+/// Constructs [`Event`] and stores it to the default outbox table.
 ///
-/// Shorthand for [`event::new()`] + [`store_event()`].
+/// Shorthand for [`Outbox::store()`] with default [`Outbox`].
 ///
 /// # Errors
 ///
 /// Will return an `Error` if there is any error occurs when storing an event to the database.
-pub async fn store<'a, E, DB, K, T, P>(
-    executor: E,
+pub async fn store<'a, A, DB, K, T, P>(
+    executor: A,
     topic: T,
     key: Option<K>,
     payload: P,
     headers: Option<Headers>,
-) -> Result<(), Error>
+) -> Result<Uuid, Error>
 where
-    E: Executor<'a, Database = DB>,
-    DB: sqlx::database::Database,
-    <DB as HasArguments<'a>>::Arguments: IntoArguments<'a, DB>,
+    A: Acquire<'a, Database = DB>,
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
     K: ToString + Default,
     P: AsBytesRef,
     T: ToString,
-    DateTime<Utc>: Type<DB> + Encode<'a, DB>,
-    String: Type<DB> + Encode<'a, DB>,
-    Vec<u8>: Type<DB> + Encode<'a, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
 {
-    store_event(executor, event::new(&topic, key, &payload, headers)).await
+    Outbox::default()
+        .store(executor, topic, key, payload, headers)
+        .await
 }
 
-/// Stores multiple outgoing events to outbox table.
+/// Constructs [`Event`] with given value encoded by the codec, and stores it to the default
+/// outbox table.
+///
+/// Shorthand for [`Outbox::store_typed()`] with default [`Outbox`].
 ///
 /// # Errors
 ///
-/// Will return an [`Error`] if there is any error occurs when storing an event to the database.
-pub fn store_events<'a, E, DB>(_executor: &E, _events: &[Event]) -> Result<(), Error>
+/// Will return an `Error` if the value can't be encoded, or if there is any error occurs when
+/// storing an event to the database.
+pub async fn store_typed<'a, A, DB, C, K, T, V>(
+    executor: A,
+    codec: &C,
+    topic: T,
+    key: Option<K>,
+    value: &V,
+    headers: Option<Headers>,
+) -> Result<Uuid, Error>
 where
-    E: Executor<'a, Database = DB>,
-    DB: sqlx::database::Database,
-    <DB as HasArguments<'a>>::Arguments: IntoArguments<'a, DB>,
-    DateTime<Utc>: Type<DB> + Encode<'a, DB>,
-    String: Type<DB> + Encode<'a, DB>,
-    Vec<u8>: Type<DB> + Encode<'a, DB>,
+    A: Acquire<'a, Database = DB>,
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    C: Codec,
+    K: ToString + Default,
+    T: ToString,
+    V: Serialize + ?Sized,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
 {
-    // TODO: Make it work
+    Outbox::default()
+        .store_typed(executor, codec, topic, key, value, headers)
+        .await
+}
 
-    Ok(())
+/// Stores multiple outgoing events to the default outbox table.
+///
+/// Shorthand for [`Outbox::store_events()`] with default [`Outbox`].
+///
+/// # Errors
+///
+/// Will return an [`Error`] if there is any error occurs when storing an event to the database.
+pub async fn store_events<'a, A, DB>(executor: A, events: &[Event]) -> Result<(), Error>
+where
+    A: Acquire<'a, Database = DB>,
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+{
+    Outbox::default().store_events(executor, events).await
 }
 
-/// Stores outgoing event to outbox table.
+/// Stores outgoing event to the default outbox table.
+///
+/// Shorthand for [`Outbox::store_event()`] with default [`Outbox`].
 ///
 /// # Errors
 ///
 /// Will return an [`Error`] if there is any error occurs when storing an event to the database.
-pub async fn store_event<'a, E, DB>(executor: E, event: Event) -> Result<(), Error>
+pub async fn store_event<'a, A, DB>(executor: A, event: Event) -> Result<Uuid, Error>
 where
-    E: Executor<'a, Database = DB>,
-    DB: sqlx::database::Database,
-    <DB as HasArguments<'a>>::Arguments: IntoArguments<'a, DB>,
-    DateTime<Utc>: Type<DB> + Encode<'a, DB>,
-    String: Type<DB> + Encode<'a, DB>,
-    Vec<u8>: Type<DB> + Encode<'a, DB>,
+    A: Acquire<'a, Database = DB>,
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
 {
-    let headers = serde_json::to_string(&event.headers).map_err(Error::HeadersEncoding)?;
-
-    #[cfg(feature = "mysql")]
-    let query = r#"
-        INSERT INTO panacea_outbox (
-            topic, key, payload, headers, created_at
-        ) VALUES (?, ?, ?, ?, NOW())
-    "#;
-
-    #[cfg(feature = "postgresql")]
-    let query = r#"
-        INSERT INTO panacea_outbox (
-            topic, key, payload, headers, created_at
-        ) VALUES ($1, $2, $3, $4, NOW())
-    "#;
-
-    #[cfg(feature = "sqlite")]
-    let query = r#"
-        INSERT INTO panacea_outbox (
-            topic, key, payload, headers, created_at
-        ) VALUES ($1, $2, $3, $4, datetime('now'))
-    "#;
-
-    sqlx::query(query)
-        .bind(event.topic)
-        .bind(event.key.unwrap_or_default())
-        .bind(event.payload)
-        .bind(headers)
-        .execute(executor)
-        .await?;
-
-    Ok(())
+    Outbox::default().store_event(executor, event).await
+}
+
+/// Event, encoded to be stored to the outbox table.
+struct EncodedEvent {
+    /// Id of the event, generated for events without it.
+    id: Uuid,
+    payload: Vec<u8>,
+    /// Serialized headers.
+    headers: String,
+    /// Values of the header columns (see [`Outbox::with_header_column()`]).
+    header_values: Vec<Option<String>>,
+}
+
+/// Number of bind parameters, used by a single outbox row, besides the header columns.
+const BIND_PARAMS_PER_ROW: usize = 8;
+
+/// Inserts events to the outbox table, followed by values of rows (see
+/// [`Outbox::row_values()`]).
+const INSERT_EVENTS: &str = r#"
+    INSERT INTO {outbox} (
+        {id}, {topic}, {key}, {payload}, {headers}, {deliver_at}, {expires_at},
+        {idempotency_key}, {header_columns}, {created_at}
+    ) VALUES "#;
+
+impl Outbox {
+    /// Constructs [`Event`] and stores it to the outbox table.
+    ///
+    /// Shorthand for [`event::new()`] + [`Outbox::store_event()`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an `Error` if there is any error occurs when storing an event to the database.
+    pub async fn store<'a, A, DB, K, T, P>(
+        &self,
+        executor: A,
+        topic: T,
+        key: Option<K>,
+        payload: P,
+        headers: Option<Headers>,
+    ) -> Result<Uuid, Error>
+    where
+        A: Acquire<'a, Database = DB>,
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        K: ToString + Default,
+        P: AsBytesRef,
+        T: ToString,
+        for<'q> String: Type<DB> + Encode<'q, DB>,
+        for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+    {
+        self.store_event(executor, event::new(&topic, key, &payload, headers))
+            .await
+    }
+
+    /// Constructs [`Event`] with given value encoded by the codec, and stores it to the outbox
+    /// table. Codec's content type is recorded in the [`codec::CONTENT_TYPE_HEADER`], so
+    /// consumers can decode the event with [`Event::decode()`].
+    ///
+    /// Shorthand for [`event::new_typed()`] + [`Outbox::store_event()`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an `Error` if the value can't be encoded, or if there is any error occurs
+    /// when storing an event to the database.
+    pub async fn store_typed<'a, A, DB, C, K, T, V>(
+        &self,
+        executor: A,
+        codec: &C,
+        topic: T,
+        key: Option<K>,
+        value: &V,
+        headers: Option<Headers>,
+    ) -> Result<Uuid, Error>
+    where
+        A: Acquire<'a, Database = DB>,
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        C: Codec,
+        K: ToString + Default,
+        T: ToString,
+        V: Serialize + ?Sized,
+        for<'q> String: Type<DB> + Encode<'q, DB>,
+        for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+    {
+        let event =
+            event::new_typed(codec, &topic, key, value, headers).map_err(Error::PayloadEncoding)?;
+
+        self.store_event(executor, event).await
+    }
+
+    /// Stores multiple outgoing events to the outbox table.
+    ///
+    /// Events are inserted with multi-row `INSERT` statements, split into chunks that fit into
+    /// the bind parameters limit of the database, so in most cases it takes a single round trip.
+    /// Pass a transaction to make sure that either all or none of the events are stored.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if there is any error occurs when storing an event to the
+    /// database.
+    pub async fn store_events<'a, A, DB>(&self, executor: A, events: &[Event]) -> Result<(), Error>
+    where
+        A: Acquire<'a, Database = DB>,
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> String: Type<DB> + Encode<'q, DB>,
+        for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+    {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let insert = self.sql::<DB>(INSERT_EVENTS);
+
+        let mut conn = executor.acquire().await?;
+
+        let params_per_row = self.bind_params_per_row();
+        for chunk in events.chunks(DB::MAX_BIND_PARAMS / params_per_row) {
+            let rows: Vec<_> = (0..chunk.len())
+                .map(|i| self.row_values::<DB>(i * params_per_row + 1))
+                .collect();
+            let mut sql = format!("{insert}{}", rows.join(", "));
+
+            if chunk.iter().any(|event| event.idempotency_key.is_some()) {
+                sql = DB::ignore_conflicts(&sql, &self.sql::<DB>("{idempotency_key}"));
+            }
+            let sql = DB::with_notification(self, sql);
+
+            let mut query = sqlx::query(&sql);
+            for event in chunk {
+                let EncodedEvent {
+                    id,
+                    payload,
+                    headers,
+                    header_values,
+                } = self.encode_payload(event)?;
+
+                query = query
+                    .bind(id)
+                    .bind(event.topic.clone())
+                    .bind(event.key.clone().unwrap_or_default())
+                    .bind(payload)
+                    .bind(headers)
+                    .bind(event.deliver_at)
+                    .bind(event.expires_at)
+                    .bind(event.idempotency_key.clone());
+                for value in header_values {
+                    query = query.bind(value);
+                }
+            }
+
+            query.execute(&mut *conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores outgoing event to the outbox table.
+    ///
+    /// On PostgreSQL, [`Outbox::notify_channel()`] is notified about the event within the same
+    /// statement. Events with [`Event::deliver_at`] are held back by the relay until that time,
+    /// and events with [`Event::expires_at`] are discarded by the relay after that time.
+    ///
+    /// Events with [`Event::idempotency_key`] are stored only once: storing another event with
+    /// the same key is a no-op, that returns id of the event stored first. Returns id of the
+    /// stored event.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if there is any error occurs when storing an event to the
+    /// database.
+    pub async fn store_event<'a, A, DB>(&self, executor: A, event: Event) -> Result<Uuid, Error>
+    where
+        A: Acquire<'a, Database = DB>,
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> String: Type<DB> + Encode<'q, DB>,
+        for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+    {
+        let mut conn = executor.acquire().await?;
+
+        self.insert_event(&mut conn, event).await
+    }
+
+    /// Stores outgoing event to the outbox table with given connection. See
+    /// [`Outbox::store_event()`].
+    ///
+    /// Takes a connection instead of [`Acquire`], so it can be called from futures, that must be
+    /// `Send` for any lifetime (e.g. the worker's one).
+    pub(crate) async fn insert_event<DB>(
+        &self,
+        conn: &mut <DB as sqlx::database::Database>::Connection,
+        event: Event,
+    ) -> Result<Uuid, Error>
+    where
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::database::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'q> String: Type<DB> + Encode<'q, DB>,
+        for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+        for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+        usize: ColumnIndex<<DB as sqlx::database::Database>::Row>,
+        for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+        for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+    {
+        let EncodedEvent {
+            id,
+            payload,
+            headers,
+            header_values,
+        } = self.encode_payload(&event)?;
+
+        let mut insert = format!(
+            "{}{}",
+            self.sql::<DB>(INSERT_EVENTS),
+            self.row_values::<DB>(1)
+        );
+        if event.idempotency_key.is_some() {
+            insert = DB::ignore_conflicts(&insert, &self.sql::<DB>("{idempotency_key}"));
+        }
+        let query = DB::with_notification(self, insert);
+
+        let mut query = sqlx::query(&query)
+            .bind(id)
+            .bind(event.topic)
+            .bind(event.key.unwrap_or_default())
+            .bind(payload)
+            .bind(headers)
+            .bind(event.deliver_at)
+            .bind(event.expires_at)
+            .bind(event.idempotency_key.clone());
+        for value in header_values {
+            query = query.bind(value);
+        }
+        query.execute(&mut *conn).await?;
+
+        // The insert is a no-op for a duplicate key, so the event stored first is looked up
+        match event.idempotency_key {
+            Some(idempotency_key) => Ok(sqlx::query_scalar(
+                &self.sql::<DB>("SELECT {id} FROM {outbox} WHERE {idempotency_key} = $1"),
+            )
+            .bind(idempotency_key)
+            .fetch_one(conn)
+            .await?),
+            None => Ok(id),
+        }
+    }
+
+    /// Converts row of the outbox table to [`Event`], reversing encryption and compression of
+    /// its payload with [`Outbox::open_event()`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`event::Error`] if headers are malformed, or if the payload can't be
+    /// decrypted or decompressed.
+    pub fn event_from_row(&self, row: EventRow) -> Result<Event, event::Error> {
+        let mut headers: Headers =
+            serde_json::from_str(&row.headers).map_err(event::Error::MalformedHeaders)?;
+        if let Some(event_type) = row.event_type {
+            headers
+                .entry(event::EVENT_TYPE_HEADER.to_string())
+                .or_insert(event_type);
+        }
+
+        self.open_event(Event {
+            id: row.id,
+            sequence: Some(row.sequence),
+            topic: row.topic,
+            key: row.key,
+            payload: row.payload,
+            headers,
+            created_at: row.created_at,
+            deliver_at: row.deliver_at,
+            expires_at: row.expires_at,
+            idempotency_key: row.idempotency_key,
+        })
+    }
+
+    /// Reverses encryption and compression of the event payload, removing headers that describe
+    /// them. Events without such headers are returned as is.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`event::Error`] if the payload can't be decrypted (e.g. encryption is not
+    /// configured, or the key is unknown), or decompressed.
+    pub fn open_event(&self, mut event: Event) -> Result<Event, event::Error> {
+        if event.headers.contains_key(event::ENCRYPTION_KEY_ID_HEADER) {
+            event.payload = self.decrypt(event.id, &event.payload, &mut event.headers)?;
+        }
+
+        if let Some(name) = event.headers.remove(event::CONTENT_ENCODING_HEADER) {
+            event.payload = Encoding::from_name(&name)
+                .ok_or(event::Error::UnsupportedContentEncoding(name))?
+                .decompress(&event.payload)
+                .map_err(event::Error::Decompression)?;
+        }
+
+        Ok(event)
+    }
+
+    /// Returns number of bind parameters, used by a single outbox row.
+    fn bind_params_per_row(&self) -> usize {
+        BIND_PARAMS_PER_ROW + self.column_headers().count()
+    }
+
+    /// Returns values of a single row of [`INSERT_EVENTS`], with bind parameters starting from
+    /// given index, in order of [`EncodedEvent`] binding.
+    fn row_values<DB: Dialect>(&self, first: usize) -> String {
+        let values: Vec<_> = (first..first + self.bind_params_per_row())
+            .map(|index| match index - first {
+                // Headers
+                4 => DB::json(&DB::placeholder(index)),
+                _ => DB::placeholder(index),
+            })
+            .collect();
+
+        format!("({}, {})", values.join(", "), DB::NOW)
+    }
+
+    /// Encodes payload and headers of the event, as they should be stored to the outbox table.
+    /// Payloads, that are encoded already (e.g. of events, that can't be opened), are kept as is.
+    fn encode_payload(&self, event: &Event) -> Result<EncodedEvent, Error> {
+        let id = event_id(event);
+        let mut payload = event.payload.clone();
+        let mut headers = event.headers.clone();
+        let is_encoded = headers.contains_key(event::CONTENT_ENCODING_HEADER)
+            || headers.contains_key(event::ENCRYPTION_KEY_ID_HEADER);
+
+        #[cfg(feature = "tracing")]
+        crate::trace::inject(&mut headers);
+
+        if let Some(compression) = self
+            .compression()
+            .filter(|compression| !is_encoded && compression.applies_to(event))
+        {
+            let encoding = compression.encoding();
+            payload = encoding.compress(&payload).map_err(Error::Compression)?;
+            headers.insert(
+                event::CONTENT_ENCODING_HEADER.to_string(),
+                encoding.name().to_string(),
+            );
+        }
+
+        #[cfg(feature = "encryption")]
+        if let Some(encryption) = self.encryption().filter(|_| !is_encoded) {
+            payload = encryption
+                .encrypt(id, &payload, &mut headers)
+                .map_err(Error::Encryption)?;
+        }
+
+        let header_values = self
+            .column_headers()
+            .map(|header| headers.get(header).cloned())
+            .collect();
+        let headers = serde_json::to_string(&headers).map_err(Error::HeadersEncoding)?;
+
+        Ok(EncodedEvent {
+            id,
+            payload,
+            headers,
+            header_values,
+        })
+    }
+
+    #[cfg(feature = "encryption")]
+    fn decrypt(
+        &self,
+        id: Uuid,
+        payload: &[u8],
+        headers: &mut Headers,
+    ) -> Result<Vec<u8>, event::Error> {
+        let encryption = self.encryption().ok_or_else(|| {
+            event::Error::Decryption(anyhow::anyhow!("encryption is not configured"))
+        })?;
+
+        encryption
+            .decrypt(id, payload, headers)
+            .map_err(|err| event::Error::Decryption(err.into()))
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    #[allow(clippy::unused_self)]
+    fn decrypt(
+        &self,
+        _id: Uuid,
+        _payload: &[u8],
+        _headers: &mut Headers,
+    ) -> Result<Vec<u8>, event::Error> {
+        Err(event::Error::Decryption(anyhow::anyhow!(
+            "`encryption` feature of panacea is disabled"
+        )))
+    }
+}
+
+/// Returns id of the event, generating a new one for events without it.
+fn event_id(event: &Event) -> Uuid {
+    if event.id.is_nil() {
+        event::new_id()
+    } else {
+        event.id
+    }
 }
 
 impl TryFrom<EventRow> for Event {
     type Error = event::Error;
 
+    /// Converts row of the default outbox table, see [`Outbox::event_from_row()`].
     fn try_from(value: EventRow) -> Result<Self, Self::Error> {
-        let headers: Headers =
-            serde_json::from_str(&value.headers).map_err(Self::Error::MalformedHeaders)?;
+        Outbox::default().event_from_row(value)
+    }
+}
 
-        Ok(Self {
-            topic: value.topic,
-            key: value.key,
-            payload: value.payload,
-            headers,
-            created_at: value.created_at,
-        })
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use sqlx::{Connection, SqliteConnection};
+
+    use super::*;
+
+    async fn connect() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&mut conn).await.expect("Can't migrate");
+
+        conn
+    }
+
+    async fn count(conn: &mut SqliteConnection) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM panacea_outbox")
+            .fetch_one(conn)
+            .await
+            .expect("Can't count events")
+    }
+
+    #[async_std::test]
+    async fn store_events_inserts_all_events() {
+        let mut conn = connect().await;
+        let events: Vec<Event> = (0..3)
+            .map(|i| event::new(&"panacea.test", Some(i), &"payload", None))
+            .collect();
+
+        store_events(&mut conn, &events)
+            .await
+            .expect("Can't store events");
+
+        let rows: Vec<EventRow> = sqlx::query_as("SELECT * FROM panacea_outbox ORDER BY key")
+            .fetch_all(&mut conn)
+            .await
+            .expect("Can't fetch events");
+        let keys: Vec<_> = rows.iter().map(|row| row.key.as_deref()).collect();
+
+        assert_eq!(keys, vec![Some("0"), Some("1"), Some("2")]);
+    }
+
+    #[async_std::test]
+    async fn store_events_splits_events_into_chunks() {
+        let mut conn = connect().await;
+        let chunk_size = sqlx::Sqlite::MAX_BIND_PARAMS / Outbox::default().bind_params_per_row();
+        let events: Vec<Event> = (0..=chunk_size)
+            .map(|i| event::new(&"panacea.test", Some(i), &"payload", None))
+            .collect();
+
+        store_events(&mut conn, &events)
+            .await
+            .expect("Can't store events");
+
+        assert_eq!(count(&mut conn).await, events.len() as i64);
+    }
+
+    #[async_std::test]
+    async fn store_events_accepts_empty_slice() {
+        let mut conn = connect().await;
+
+        store_events(&mut conn, &[])
+            .await
+            .expect("Can't store events");
+
+        assert_eq!(count(&mut conn).await, 0);
+    }
+
+    #[async_std::test]
+    async fn stored_events_keep_their_ids() {
+        let mut conn = connect().await;
+        let event = event::new(&"panacea.test", Some(1), &"payload", None);
+        let id = event.id;
+
+        store_event(&mut conn, event)
+            .await
+            .expect("Can't store event");
+        store_event(&mut conn, Event::default())
+            .await
+            .expect("Can't store event without id");
+
+        let rows: Vec<EventRow> = sqlx::query_as("SELECT * FROM panacea_outbox ORDER BY sequence")
+            .fetch_all(&mut conn)
+            .await
+            .expect("Can't fetch events");
+        let stored = Event::try_from(rows[0].clone()).expect("Can't decode event");
+
+        assert_eq!(stored.id, id);
+        assert_eq!(stored.sequence, Some(rows[0].sequence));
+        assert!(rows[0].sequence < rows[1].sequence);
+        assert!(!rows[1].id.is_nil());
+    }
+
+    #[async_std::test]
+    async fn store_event_deduplicates_by_idempotency_key() {
+        let mut conn = connect().await;
+        let event = || event::new(&"panacea.test", Some(1), &"payload", None);
+
+        let id = store_event(&mut conn, event().with_idempotency_key("key"))
+            .await
+            .expect("Can't store event");
+        let duplicate = store_event(&mut conn, event().with_idempotency_key("key"))
+            .await
+            .expect("Can't store duplicate event");
+        store_event(&mut conn, event())
+            .await
+            .expect("Can't store event without idempotency key");
+        store_event(&mut conn, event())
+            .await
+            .expect("Can't store event without idempotency key");
+
+        assert_eq!(duplicate, id);
+        assert_eq!(count(&mut conn).await, 3);
+    }
+
+    #[async_std::test]
+    async fn store_typed_records_content_type() {
+        let mut conn = connect().await;
+        let value = HashMap::from([("answer".to_string(), 42)]);
+
+        store_typed(
+            &mut conn,
+            &codec::Json,
+            "panacea.test",
+            Some(0),
+            &value,
+            None,
+        )
+        .await
+        .expect("Can't store event");
+
+        let row: EventRow = sqlx::query_as("SELECT * FROM panacea_outbox")
+            .fetch_one(&mut conn)
+            .await
+            .expect("Can't fetch event");
+        let stored = Event::try_from(row).expect("Can't decode event");
+
+        assert_eq!(
+            stored.headers.get(codec::CONTENT_TYPE_HEADER),
+            Some(&"application/json".to_string())
+        );
+        assert_eq!(
+            stored
+                .decode::<HashMap<String, i32>>()
+                .expect("Can't decode payload"),
+            value
+        );
+    }
+
+    #[async_std::test]
+    async fn compresses_payloads_of_configured_events() {
+        let mut conn = connect().await;
+        let payload = "Hello, Panacea! ".repeat(100);
+        let outbox = Outbox::new().with_compression(
+            Compression::new(Encoding::Zstd)
+                .with_min_size(100)
+                .with_topics(["documents"]),
+        );
+
+        for (topic, payload) in [
+            ("documents", payload.as_str()),
+            ("documents", "small"),
+            ("users", payload.as_str()),
+        ] {
+            outbox
+                .store(&mut conn, topic, None::<String>, payload, None)
+                .await
+                .expect("Can't store event");
+        }
+
+        let rows: Vec<EventRow> = sqlx::query_as("SELECT * FROM panacea_outbox ORDER BY sequence")
+            .fetch_all(&mut conn)
+            .await
+            .expect("Can't fetch events");
+        let compressed: Vec<_> = rows
+            .iter()
+            .map(|row| row.headers.contains(event::CONTENT_ENCODING_HEADER))
+            .collect();
+        assert_eq!(compressed, vec![true, false, false]);
+        assert!(rows[0].payload.len() < payload.len());
+
+        let stored = Event::try_from(rows[0].clone()).expect("Can't decode event");
+        assert_eq!(stored.payload, payload.as_bytes());
+        assert!(!stored.headers.contains_key(event::CONTENT_ENCODING_HEADER));
     }
 }
@@ -0,0 +1,729 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use sqlx::{database::HasArguments, Encode, Executor, FromRow, IntoArguments, Pool, Type};
+
+use panacea_types::{event, publisher::Publisher};
+
+use super::{error_chain, Backoff, Dialect, Error, EventRow, Outbox};
+
+/// What to do with an outbox row after its event is published.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnPublished {
+    /// Set `published_at` column of the row to the current timestamp.
+    #[default]
+    Mark,
+    /// Delete the row from the outbox table.
+    Delete,
+}
+
+/// Drains outbox table, handing stored events over to a [`Publisher`].
+pub struct Relay<DB: sqlx::Database, P: Publisher> {
+    /// Database connection pool.
+    db: Pool<DB>,
+    /// Outbox table to drain.
+    outbox: Outbox,
+    /// [`Publisher`] instance.
+    publisher: P,
+    /// How long to wait before polling the outbox table again, when there is nothing to publish.
+    poll_interval: Duration,
+    /// Maximum number of rows, fetched from the outbox table at once.
+    batch_size: u32,
+    /// What to do with published rows.
+    on_published: OnPublished,
+    /// Relay activeness flag.
+    is_active: Arc<AtomicBool>,
+    /// Identifies this relay instance in claimed rows.
+    instance_id: String,
+    /// For how long rows stay claimed by this relay instance.
+    lease_duration: chrono::Duration,
+    /// Delays between attempts to publish events, that have failed to publish.
+    backoff: Backoff,
+    /// Listens for notifications about stored events.
+    #[cfg(feature = "postgres")]
+    listener: Option<sqlx::postgres::PgListener>,
+}
+
+impl<DB, P> Relay<DB, P>
+where
+    DB: Dialect,
+    P: Publisher,
+    for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'q> i64: Type<DB> + Encode<'q, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> DateTime<Utc>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+{
+    pub fn new(db: Pool<DB>, publisher: P) -> Self {
+        Self {
+            db,
+            outbox: Outbox::default(),
+            publisher,
+            poll_interval: Duration::from_secs(1),
+            batch_size: 100,
+            on_published: OnPublished::default(),
+            is_active: Arc::new(AtomicBool::new(true)),
+            instance_id: event::new_id().to_string(),
+            lease_duration: chrono::Duration::seconds(30),
+            backoff: Backoff::default(),
+            #[cfg(feature = "postgres")]
+            listener: None,
+        }
+    }
+
+    /// Relays events until the activeness flag is unset.
+    pub async fn run(mut self) {
+        println!("Starting events relaying...");
+
+        while self.is_active.load(Ordering::SeqCst) {
+            match self.relay_batch().await {
+                Ok(0) => self.wait_for_events().await,
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("Can't relay events: {err}");
+                    async_std::task::sleep(self.poll_interval).await;
+                }
+            }
+        }
+    }
+
+    /// Waits until it's time to poll the outbox table again.
+    async fn wait_for_events(&mut self) {
+        #[cfg(feature = "postgres")]
+        if let Some(listener) = &mut self.listener {
+            // Wakes up on notification, falling back to polling if nothing arrives in time.
+            // When the connection is lost (`Ok(None)`), the listener reconnects on the next call,
+            // and the table is polled right away, as notifications could be missed meanwhile.
+            match async_std::future::timeout(self.poll_interval, listener.try_recv()).await {
+                Ok(Ok(_)) | Err(_) => {}
+                Ok(Err(err)) => {
+                    eprintln!("Can't receive outbox notification: {err}");
+                    async_std::task::sleep(self.poll_interval).await;
+                }
+            }
+
+            return;
+        }
+
+        async_std::task::sleep(self.poll_interval).await;
+    }
+
+    /// Publishes a single batch of unpublished events in the order they were stored.
+    /// Stops at the first event that can't be published. The failed attempt is recorded in the
+    /// `attempts` and `last_error` columns, and the event is retried after a delay (see
+    /// [`Self::with_backoff`]), or dead-lettered in the `dead_lettered_at` column once attempts
+    /// are exhausted.
+    /// Scheduled events are skipped until their [`panacea_types::Event::deliver_at`] time comes,
+    /// and expired events (see [`panacea_types::Event::expires_at`]) are marked as such in the
+    /// `expired_at` column instead of being published.
+    ///
+    /// Rows of the batch are claimed, so several relay instances can drain the same outbox
+    /// table without publishing an event twice or blocking each other: with `FOR UPDATE SKIP
+    /// LOCKED` on PostgreSQL and MySQL 8, and with a lease (see [`Self::with_lease_duration`]) on
+    /// SQLite. Note that events are only ordered within a single relay instance then.
+    ///
+    /// Returns the number of relayed events, including expired ones.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if rows can't be fetched or updated, or if an event can't be
+    /// decoded or published.
+    pub async fn relay_batch(&self) -> Result<usize, Error> {
+        if DB::SKIP_LOCKED {
+            self.relay_locked_batch().await
+        } else {
+            self.relay_leased_batch().await
+        }
+    }
+
+    /// Publishes a single batch of events, claiming its rows with `FOR UPDATE SKIP LOCKED`.
+    async fn relay_locked_batch(&self) -> Result<usize, Error> {
+        let query = r#"
+            SELECT {event_columns}
+            FROM {outbox}
+            WHERE {published_at} IS NULL
+                AND {expired_at} IS NULL
+                AND {dead_lettered_at} IS NULL
+                AND ({deliver_at} IS NULL OR {deliver_at} <= $1)
+                AND ({next_attempt_at} IS NULL OR {next_attempt_at} <= $2)
+            ORDER BY {sequence}
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+        "#;
+
+        // Row locks are held until the transaction ends, so rows stay claimed while their
+        // events are being published.
+        let mut tx = self.db.begin().await?;
+
+        let now = Utc::now();
+        let rows: Vec<EventRow> = sqlx::query_as(&self.outbox.sql::<DB>(query))
+            .bind(now)
+            .bind(now)
+            .bind(i64::from(self.batch_size))
+            .fetch_all(&mut *tx)
+            .await?;
+
+        let mut published = 0;
+        let result = self.publish_rows(&mut tx, rows, &mut published).await;
+
+        // Rows, published before a failure, should be committed as published anyway.
+        tx.commit().await?;
+
+        result.map(|()| published)
+    }
+
+    /// Publishes a single batch of events, claiming its rows with a lease.
+    async fn relay_leased_batch(&self) -> Result<usize, Error> {
+        let now = Utc::now();
+        let claimed_until = now + self.lease_duration;
+
+        // Claim unpublished rows, that are not claimed yet or whose lease has expired
+        // (e.g. because the instance, that claimed them, has crashed).
+        sqlx::query(&self.outbox.sql::<DB>(
+            r#"
+                UPDATE {outbox}
+                SET {claimed_by} = $1, {claimed_until} = $2
+                WHERE {sequence} IN (
+                    SELECT {sequence}
+                    FROM {outbox}
+                    WHERE {published_at} IS NULL
+                        AND {expired_at} IS NULL
+                        AND {dead_lettered_at} IS NULL
+                        AND ({claimed_until} IS NULL OR {claimed_until} < $3)
+                        AND ({deliver_at} IS NULL OR {deliver_at} <= $4)
+                        AND ({next_attempt_at} IS NULL OR {next_attempt_at} <= $5)
+                    ORDER BY {sequence}
+                    LIMIT $6
+                )
+            "#,
+        ))
+        .bind(&self.instance_id)
+        .bind(claimed_until)
+        .bind(now)
+        .bind(now)
+        .bind(now)
+        .bind(i64::from(self.batch_size))
+        .execute(&self.db)
+        .await?;
+
+        let rows: Vec<EventRow> = sqlx::query_as(&self.outbox.sql::<DB>(
+            r#"
+                SELECT {event_columns}
+                FROM {outbox}
+                WHERE {published_at} IS NULL
+                    AND {expired_at} IS NULL
+                    AND {claimed_by} = $1
+                    AND {claimed_until} = $2
+                ORDER BY {sequence}
+            "#,
+        ))
+        .bind(&self.instance_id)
+        .bind(claimed_until)
+        .fetch_all(&self.db)
+        .await?;
+
+        let mut conn = self.db.acquire().await?;
+        let mut published = 0;
+        let result = self.publish_rows(&mut conn, rows, &mut published).await;
+
+        if result.is_err() {
+            // Release rows, left unpublished, so they can be retried without waiting for the
+            // lease to expire.
+            sqlx::query(&self.outbox.sql::<DB>(
+                r#"
+                    UPDATE {outbox}
+                    SET {claimed_by} = NULL, {claimed_until} = NULL
+                    WHERE {published_at} IS NULL AND {claimed_by} = $1
+                "#,
+            ))
+            .bind(&self.instance_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        result.map(|()| published)
+    }
+
+    /// Publishes events from given rows in order, marking or deleting published rows, and
+    /// marking rows of expired events. Increments `published` counter for every relayed event.
+    /// Records failed attempt of the first event, that can't be decoded or published.
+    async fn publish_rows(
+        &self,
+        conn: &mut <DB as sqlx::Database>::Connection,
+        rows: Vec<EventRow>,
+        published: &mut usize,
+    ) -> Result<(), Error> {
+        for row in rows {
+            let sequence = row.sequence;
+            let attempts = row.attempts;
+            let mut event = match self.outbox.event_from_row(row) {
+                Ok(event) => event,
+                Err(err) => {
+                    let err = Error::EventDecoding(err);
+                    self.fail(&mut *conn, sequence, attempts, &err).await?;
+
+                    return Err(err);
+                }
+            };
+
+            if event.is_expired_at(Utc::now()) {
+                self.expire(&mut *conn, sequence).await?;
+                *published += 1;
+                continue;
+            }
+
+            event
+                .headers
+                .insert(event::ID_HEADER.to_string(), event.id.to_string());
+            if let Some(expires_at) = event.expires_at {
+                event.headers.insert(
+                    event::EXPIRES_AT_HEADER.to_string(),
+                    expires_at.to_rfc3339(),
+                );
+            }
+
+            if let Err(err) = self.publisher.publish(&event).await {
+                let err = Error::Publishing(err);
+                self.fail(&mut *conn, sequence, attempts, &err).await?;
+
+                return Err(err);
+            }
+            self.complete(&mut *conn, sequence).await?;
+
+            *published += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Marks or deletes published row, depending on [`OnPublished`] setting.
+    async fn complete(
+        &self,
+        conn: &mut <DB as sqlx::Database>::Connection,
+        sequence: i64,
+    ) -> Result<(), Error> {
+        let query = match self.on_published {
+            OnPublished::Mark => "UPDATE {outbox} SET {published_at} = {now} WHERE {sequence} = $1",
+            OnPublished::Delete => "DELETE FROM {outbox} WHERE {sequence} = $1",
+        };
+
+        sqlx::query(&self.outbox.sql::<DB>(query))
+            .bind(sequence)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Marks row of expired event, so it's not relayed anymore.
+    async fn expire(
+        &self,
+        conn: &mut <DB as sqlx::Database>::Connection,
+        sequence: i64,
+    ) -> Result<(), Error> {
+        let query = "UPDATE {outbox} SET {expired_at} = {now} WHERE {sequence} = $1";
+
+        sqlx::query(&self.outbox.sql::<DB>(query))
+            .bind(sequence)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records failed attempt to publish the row, scheduling the next attempt, or dead-lettering
+    /// the row (see [`super::DeadLetter`]) if attempts are exhausted.
+    async fn fail(
+        &self,
+        conn: &mut <DB as sqlx::Database>::Connection,
+        sequence: i64,
+        attempts: i32,
+        err: &Error,
+    ) -> Result<(), Error> {
+        let attempts = u32::try_from(attempts).unwrap_or_default() + 1;
+        let now = Utc::now();
+        let next_attempt_at = chrono::Duration::from_std(self.backoff.delay(attempts))
+            .map_or(now, |delay| now + delay);
+        let is_dead_lettered = self.backoff.is_exhausted(attempts);
+
+        // Dead-lettering time is compared with the retention cutoff, so it's set by the
+        // database, the same way as the publishing time
+        let query = r#"
+            UPDATE {outbox}
+            SET {attempts} = {attempts} + 1,
+                {last_error} = $1,
+                {next_attempt_at} = $2,
+                {dead_lettered_at} = {dead_lettered_at_value}
+            WHERE {sequence} = $3
+        "#
+        .replace(
+            "{dead_lettered_at_value}",
+            if is_dead_lettered { "{now}" } else { "NULL" },
+        );
+
+        sqlx::query(&self.outbox.sql::<DB>(&query))
+            .bind(error_chain(err))
+            .bind(next_attempt_at)
+            .bind(sequence)
+            .execute(&mut *conn)
+            .await?;
+
+        if is_dead_lettered {
+            eprintln!("Dead-lettering outbox row #{sequence} after {attempts} failed attempts");
+            self.outbox.dead_letter_row(conn, sequence).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets outbox table to drain. Defaults to [`Outbox::default`].
+    #[must_use]
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = outbox;
+
+        self
+    }
+
+    /// Sets how long to wait before polling the outbox table again, when there is nothing to
+    /// publish. Defaults to 1 second.
+    #[must_use]
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+
+        self
+    }
+
+    /// Sets maximum number of rows, fetched from the outbox table at once. Defaults to 100.
+    #[must_use]
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+
+        self
+    }
+
+    /// Sets what to do with published rows. Defaults to [`OnPublished::Mark`].
+    #[must_use]
+    pub fn with_on_published(mut self, on_published: OnPublished) -> Self {
+        self.on_published = on_published;
+
+        self
+    }
+
+    /// Sets for how long claimed rows can't be claimed by other relay instances. Should be long
+    /// enough to publish a whole batch. Defaults to 30 seconds. Only used on backends without
+    /// `SKIP LOCKED` support (see [`Dialect::SKIP_LOCKED`]).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is out of range.
+    #[must_use]
+    pub fn with_lease_duration(mut self, lease_duration: Duration) -> Self {
+        self.lease_duration =
+            chrono::Duration::from_std(lease_duration).expect("lease duration is out of range");
+
+        self
+    }
+
+    /// Sets delays between attempts to publish events, that have failed to publish, and after
+    /// how many attempts they are dead-lettered. Defaults to [`Backoff::default`].
+    #[must_use]
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+
+        self
+    }
+
+    #[must_use]
+    pub fn with_activeness_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.is_active = flag;
+
+        self
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl<P: Publisher> Relay<sqlx::Postgres, P> {
+    /// Makes the relay `LISTEN` to [`Outbox::notify_channel`], so it wakes up right after events
+    /// are committed to the outbox table, instead of waiting for the next poll. Polling with
+    /// `poll_interval` stays as a fallback, e.g. for notifications missed while reconnecting.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the listener can't connect to the database.
+    ///
+    /// Should be called after [`Self::with_outbox`], as the channel depends on the outbox table.
+    pub async fn with_notifications(mut self) -> Result<Self, Error> {
+        let mut listener = sqlx::postgres::PgListener::connect_with(&self.db).await?;
+        listener.listen(&self.outbox.notify_channel()).await?;
+        self.listener = Some(listener);
+
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+    use super::*;
+    use crate::outbox::{migrate, store, Columns};
+    use panacea_types::{
+        publisher::{self, InMemoryPublisher},
+        Event,
+    };
+
+    async fn connect() -> SqlitePool {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&db).await.expect("Can't migrate");
+
+        for key in 0..3 {
+            store(&db, "panacea.test", Some(key), "payload", None)
+                .await
+                .expect("Can't store event");
+        }
+
+        db
+    }
+
+    async fn count(db: &SqlitePool, condition: &str) -> i64 {
+        sqlx::query_scalar(&format!(
+            "SELECT COUNT(*) FROM panacea_outbox WHERE {condition}"
+        ))
+        .fetch_one(db)
+        .await
+        .expect("Can't count events")
+    }
+
+    fn keys(events: &[Event]) -> Vec<Option<&str>> {
+        events.iter().map(|event| event.key.as_deref()).collect()
+    }
+
+    /// Fails to publish events with the given key.
+    struct FailingPublisher {
+        key: &'static str,
+        inner: InMemoryPublisher,
+    }
+
+    #[async_trait]
+    impl Publisher for FailingPublisher {
+        async fn publish(&self, event: &Event) -> Result<(), publisher::Error> {
+            if event.key.as_deref() == Some(self.key) {
+                return Err(anyhow!("broker is unavailable").into());
+            }
+
+            self.inner.publish(event).await
+        }
+    }
+
+    #[async_std::test]
+    async fn publishes_events_in_order_and_marks_rows() {
+        let db = connect().await;
+        let publisher = InMemoryPublisher::new();
+        let relay = Relay::new(db.clone(), publisher.clone());
+
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 3);
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+        assert_eq!(
+            keys(&publisher.events()),
+            vec![Some("0"), Some("1"), Some("2")]
+        );
+        assert_eq!(count(&db, "published_at IS NOT NULL").await, 3);
+    }
+
+    #[async_std::test]
+    async fn adds_event_id_header() {
+        let db = connect().await;
+        let publisher = InMemoryPublisher::new();
+        let relay = Relay::new(db, publisher.clone());
+
+        relay.relay_batch().await.expect("Can't relay");
+
+        for event in publisher.events() {
+            assert_eq!(
+                event.headers.get(event::ID_HEADER),
+                Some(&event.id.to_string())
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn respects_batch_size() {
+        let db = connect().await;
+        let publisher = InMemoryPublisher::new();
+        let relay = Relay::new(db.clone(), publisher.clone()).with_batch_size(2);
+
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 2);
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 1);
+        assert_eq!(publisher.events().len(), 3);
+    }
+
+    #[async_std::test]
+    async fn deletes_published_rows() {
+        let db = connect().await;
+        let relay =
+            Relay::new(db.clone(), InMemoryPublisher::new()).with_on_published(OnPublished::Delete);
+
+        relay.relay_batch().await.expect("Can't relay");
+
+        assert_eq!(count(&db, "1 = 1").await, 0);
+    }
+
+    #[async_std::test]
+    async fn stops_at_first_failed_event() {
+        let db = connect().await;
+        let publisher = FailingPublisher {
+            key: "1",
+            inner: InMemoryPublisher::new(),
+        };
+        let events = publisher.inner.clone();
+        let relay = Relay::new(db.clone(), publisher);
+
+        assert!(matches!(
+            relay.relay_batch().await,
+            Err(Error::Publishing(_))
+        ));
+        assert_eq!(keys(&events.events()), vec![Some("0")]);
+        assert_eq!(count(&db, "published_at IS NULL").await, 2);
+        // Unpublished rows are released right away
+        assert_eq!(
+            count(&db, "claimed_by IS NOT NULL AND published_at IS NULL").await,
+            0
+        );
+    }
+
+    #[async_std::test]
+    async fn retries_failed_events_with_backoff() {
+        let db = connect().await;
+        let publisher = FailingPublisher {
+            key: "1",
+            inner: InMemoryPublisher::new(),
+        };
+        let events = publisher.inner.clone();
+        let relay = Relay::new(db.clone(), publisher)
+            .with_backoff(Backoff::new().with_initial_delay(Duration::from_secs(60)));
+
+        assert!(relay.relay_batch().await.is_err());
+        assert_eq!(
+            count(
+                &db,
+                "attempts = 1 AND last_error = 'can''t publish event: broker is unavailable'"
+            )
+            .await,
+            1
+        );
+
+        // The failed event waits for the next attempt, without blocking the following ones
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 1);
+        assert_eq!(keys(&events.events()), vec![Some("0"), Some("2")]);
+        assert_eq!(count(&db, "published_at IS NULL").await, 1);
+    }
+
+    #[async_std::test]
+    async fn dead_letters_events_after_max_attempts() {
+        let db = connect().await;
+        let publisher = FailingPublisher {
+            key: "1",
+            inner: InMemoryPublisher::new(),
+        };
+        let relay = Relay::new(db.clone(), publisher).with_backoff(
+            Backoff::new()
+                .with_initial_delay(Duration::ZERO)
+                .with_max_attempts(2),
+        );
+
+        assert!(relay.relay_batch().await.is_err());
+        assert!(relay.relay_batch().await.is_err());
+        assert_eq!(count(&db, "dead_lettered_at IS NOT NULL").await, 1);
+
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 1);
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+        assert_eq!(count(&db, "attempts = 2 AND published_at IS NULL").await, 1);
+    }
+
+    async fn claim_all(db: &SqlitePool, claimed_until: DateTime<Utc>) {
+        sqlx::query("UPDATE panacea_outbox SET claimed_by = 'other', claimed_until = $1")
+            .bind(claimed_until)
+            .execute(db)
+            .await
+            .expect("Can't claim rows");
+    }
+
+    #[async_std::test]
+    async fn skips_rows_claimed_by_other_instances() {
+        let db = connect().await;
+        let relay = Relay::new(db.clone(), InMemoryPublisher::new());
+
+        claim_all(&db, Utc::now() + chrono::Duration::hours(1)).await;
+
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+    }
+
+    #[async_std::test]
+    async fn reclaims_expired_leases() {
+        let db = connect().await;
+        let relay = Relay::new(db.clone(), InMemoryPublisher::new());
+
+        claim_all(&db, Utc::now() - chrono::Duration::seconds(1)).await;
+
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 3);
+    }
+
+    #[async_std::test]
+    async fn instances_do_not_share_claimed_rows() {
+        let db = connect().await;
+        let first = Relay::new(db.clone(), InMemoryPublisher::new()).with_batch_size(2);
+        let second = Relay::new(db.clone(), InMemoryPublisher::new());
+
+        assert_eq!(first.relay_batch().await.expect("Can't relay"), 2);
+        assert_eq!(second.relay_batch().await.expect("Can't relay"), 1);
+    }
+
+    #[async_std::test]
+    async fn relays_configured_outbox() {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+        let outbox = Outbox::new()
+            .with_table("billing_outbox")
+            .with_columns(Columns {
+                topic: "event_topic".to_string(),
+                published_at: "relayed_at".to_string(),
+                ..Columns::default()
+            });
+        let publisher = InMemoryPublisher::new();
+        let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(outbox.clone());
+
+        outbox.migrate(&db).await.expect("Can't migrate");
+        outbox
+            .store(&db, "billing.test", Some(0), "payload", None)
+            .await
+            .expect("Can't store event");
+
+        assert_eq!(relay.relay_batch().await.expect("Can't relay"), 1);
+        assert_eq!(publisher.events()[0].topic, "billing.test");
+
+        let relayed: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM billing_outbox WHERE relayed_at IS NOT NULL")
+                .fetch_one(&db)
+                .await
+                .expect("Can't count events");
+        assert_eq!(relayed, 1);
+    }
+}
@@ -0,0 +1,514 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use sqlx::{database::HasArguments, Decode, Encode, Executor, IntoArguments, Pool, Type};
+
+use super::{Dialect, Error, Outbox};
+
+/// What to do with published rows, that are older than the retention period.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionAction {
+    /// Delete rows from the outbox table.
+    #[default]
+    Delete,
+    /// Move rows to the archive table of the outbox (e.g. `panacea_outbox_archive`).
+    ///
+    /// Event columns are archived, along with the publishing and dead-lettering state, but not
+    /// the transient state of the relay (leases and next attempt time). Every event is archived
+    /// once: if it's archived again (e.g. after its dead letter has been requeued), the latest
+    /// row replaces the archived one.
+    Archive,
+}
+
+/// Purges published, expired and dead-lettered rows from the outbox table, so it doesn't grow
+/// forever.
+///
+/// Rows are purged in bounded batches, every batch in its own short transaction, so purging
+/// never holds long table locks. Rows, that are neither published, expired nor dead-lettered
+/// yet, are never purged. Dead-lettered rows are purged only from the outbox table, their dead
+/// letters are kept (and can still be requeued), unless the dead letter retention period is set
+/// with [`Retention::with_dead_letter_retention_period()`].
+pub struct Retention<DB: sqlx::Database> {
+    /// Database connection pool.
+    db: Pool<DB>,
+    /// Outbox table to purge.
+    outbox: Outbox,
+    /// How long published rows are kept in the outbox table.
+    retention_period: Duration,
+    /// How long dead letters are kept in the dead letters table. Forever, if not set.
+    dead_letter_retention_period: Option<Duration>,
+    /// What to do with expired rows.
+    action: RetentionAction,
+    /// Maximum number of rows, purged in a single transaction.
+    batch_size: u32,
+    /// How long to wait between purges, when running in background.
+    interval: Duration,
+    /// Activeness flag.
+    is_active: Arc<AtomicBool>,
+}
+
+impl<DB> Retention<DB>
+where
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> i64: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    for<'r> (i64, Option<i64>): sqlx::FromRow<'r, <DB as sqlx::Database>::Row>,
+{
+    pub fn new(db: Pool<DB>) -> Self {
+        Self {
+            db,
+            outbox: Outbox::default(),
+            retention_period: Duration::from_secs(7 * 24 * 60 * 60),
+            dead_letter_retention_period: None,
+            action: RetentionAction::default(),
+            batch_size: 1000,
+            interval: Duration::from_secs(60),
+            is_active: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Purges expired rows every `interval` until the activeness flag is unset.
+    pub async fn run(self) {
+        println!("Starting outbox purging...");
+
+        while self.is_active.load(Ordering::SeqCst) {
+            if let Err(err) = self.purge().await {
+                eprintln!("Can't purge outbox: {err}");
+            }
+
+            async_std::task::sleep(self.interval).await;
+        }
+    }
+
+    /// Purges all expired rows batch by batch, and then expired dead letters, if the dead letter
+    /// retention period is set.
+    ///
+    /// Returns the number of purged rows and dead letters.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if there is any error occurs when purging rows.
+    pub async fn purge(&self) -> Result<u64, Error> {
+        let mut purged = 0;
+
+        loop {
+            match self.purge_batch().await? {
+                0 => break,
+                count => purged += count,
+            }
+        }
+
+        loop {
+            match self.purge_dead_letters_batch().await? {
+                0 => return Ok(purged),
+                count => purged += count,
+            }
+        }
+    }
+
+    /// Purges a single batch of expired rows in a transaction.
+    ///
+    /// Returns the number of purged rows.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if there is any error occurs when purging rows.
+    pub async fn purge_batch(&self) -> Result<u64, Error> {
+        // Cutoff expression depends on the backend, so it's rendered into templates separately.
+        let batch_query = r#"
+            SELECT COUNT(*), MAX({sequence}) FROM (
+                SELECT {sequence}
+                FROM {outbox}
+                WHERE {published_at} < {published_cutoff}
+                    OR {expired_at} < {expired_cutoff}
+                    OR {dead_lettered_at} < {dead_lettered_cutoff}
+                ORDER BY {sequence}
+                LIMIT $4
+            ) AS batch
+        "#
+        .replace("{published_cutoff}", &DB::seconds_ago("$1"))
+        .replace("{expired_cutoff}", &DB::seconds_ago("$2"))
+        .replace("{dead_lettered_cutoff}", &DB::seconds_ago("$3"));
+        let unarchive_query = r#"
+            DELETE FROM {archive}
+            WHERE {id} IN (
+                SELECT {id}
+                FROM {outbox}
+                WHERE {sequence} <= $1
+                    AND (
+                        {published_at} < {published_cutoff}
+                        OR {expired_at} < {expired_cutoff}
+                        OR {dead_lettered_at} < {dead_lettered_cutoff}
+                    )
+            )
+        "#
+        .replace("{published_cutoff}", &DB::seconds_ago("$2"))
+        .replace("{expired_cutoff}", &DB::seconds_ago("$3"))
+        .replace("{dead_lettered_cutoff}", &DB::seconds_ago("$4"));
+        let archive_query = r#"
+            INSERT INTO {archive} (
+                {sequence}, {id}, {topic}, {key}, {payload}, {headers}, {created_at},
+                {published_at}, {deliver_at}, {expires_at}, {expired_at}, {attempts},
+                {last_error}, {dead_lettered_at}, {idempotency_key}, {header_columns}
+            )
+            SELECT
+                {sequence}, {id}, {topic}, {key}, {payload}, {headers}, {created_at},
+                {published_at}, {deliver_at}, {expires_at}, {expired_at}, {attempts},
+                {last_error}, {dead_lettered_at}, {idempotency_key}, {header_columns}
+            FROM {outbox}
+            WHERE {sequence} <= $1
+                AND (
+                    {published_at} < {published_cutoff}
+                    OR {expired_at} < {expired_cutoff}
+                    OR {dead_lettered_at} < {dead_lettered_cutoff}
+                )
+        "#
+        .replace("{published_cutoff}", &DB::seconds_ago("$2"))
+        .replace("{expired_cutoff}", &DB::seconds_ago("$3"))
+        .replace("{dead_lettered_cutoff}", &DB::seconds_ago("$4"));
+        let delete_query = r#"
+            DELETE FROM {outbox}
+            WHERE {sequence} <= $1
+                AND (
+                    {published_at} < {published_cutoff}
+                    OR {expired_at} < {expired_cutoff}
+                    OR {dead_lettered_at} < {dead_lettered_cutoff}
+                )
+        "#
+        .replace("{published_cutoff}", &DB::seconds_ago("$2"))
+        .replace("{expired_cutoff}", &DB::seconds_ago("$3"))
+        .replace("{dead_lettered_cutoff}", &DB::seconds_ago("$4"));
+
+        let retention_period = i64::try_from(self.retention_period.as_secs()).unwrap_or(i64::MAX);
+
+        let mut tx = self.db.begin().await?;
+
+        let (count, last_sequence): (i64, Option<i64>) =
+            sqlx::query_as(&self.outbox.sql::<DB>(&batch_query))
+                .bind(retention_period)
+                .bind(retention_period)
+                .bind(retention_period)
+                .bind(i64::from(self.batch_size))
+                .fetch_one(&mut *tx)
+                .await?;
+        let Some(last_sequence) = last_sequence else {
+            return Ok(0);
+        };
+
+        if self.action == RetentionAction::Archive {
+            // Rows of requeued events have been archived before, under another sequence
+            for query in [&unarchive_query, &archive_query] {
+                sqlx::query(&self.outbox.sql::<DB>(query))
+                    .bind(last_sequence)
+                    .bind(retention_period)
+                    .bind(retention_period)
+                    .bind(retention_period)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        sqlx::query(&self.outbox.sql::<DB>(&delete_query))
+            .bind(last_sequence)
+            .bind(retention_period)
+            .bind(retention_period)
+            .bind(retention_period)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(u64::try_from(count).unwrap_or_default())
+    }
+
+    /// Deletes a single batch of dead letters, that are older than the dead letter retention
+    /// period, in a transaction. Does nothing, if the period is not set.
+    ///
+    /// Returns the number of deleted dead letters.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if there is any error occurs when deleting dead letters.
+    pub async fn purge_dead_letters_batch(&self) -> Result<u64, Error> {
+        let Some(retention_period) = self.dead_letter_retention_period else {
+            return Ok(0);
+        };
+
+        let batch_query = r#"
+            SELECT COUNT(*), MAX({sequence}) FROM (
+                SELECT {sequence}
+                FROM {dead_letters}
+                WHERE {dead_lettered_at} < {cutoff}
+                ORDER BY {sequence}
+                LIMIT $2
+            ) AS batch
+        "#
+        .replace("{cutoff}", &DB::seconds_ago("$1"));
+        let delete_query = r#"
+            DELETE FROM {dead_letters}
+            WHERE {sequence} <= $1 AND {dead_lettered_at} < {cutoff}
+        "#
+        .replace("{cutoff}", &DB::seconds_ago("$2"));
+
+        let retention_period = i64::try_from(retention_period.as_secs()).unwrap_or(i64::MAX);
+
+        let mut tx = self.db.begin().await?;
+
+        let (count, last_sequence): (i64, Option<i64>) =
+            sqlx::query_as(&self.outbox.sql::<DB>(&batch_query))
+                .bind(retention_period)
+                .bind(i64::from(self.batch_size))
+                .fetch_one(&mut *tx)
+                .await?;
+        let Some(last_sequence) = last_sequence else {
+            return Ok(0);
+        };
+
+        sqlx::query(&self.outbox.sql::<DB>(&delete_query))
+            .bind(last_sequence)
+            .bind(retention_period)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(u64::try_from(count).unwrap_or_default())
+    }
+
+    /// Sets outbox table to purge. Defaults to [`Outbox::default`].
+    #[must_use]
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = outbox;
+
+        self
+    }
+
+    /// Sets how long published, expired and dead-lettered rows are kept in the outbox table.
+    /// Defaults to 7 days.
+    #[must_use]
+    pub fn with_retention_period(mut self, retention_period: Duration) -> Self {
+        self.retention_period = retention_period;
+
+        self
+    }
+
+    /// Sets how long dead letters are kept in the dead letters table. Dead letters are kept
+    /// until they are requeued or discarded, if not set.
+    #[must_use]
+    pub fn with_dead_letter_retention_period(mut self, retention_period: Duration) -> Self {
+        self.dead_letter_retention_period = Some(retention_period);
+
+        self
+    }
+
+    /// Sets what to do with expired rows. Defaults to [`RetentionAction::Delete`].
+    #[must_use]
+    pub fn with_action(mut self, action: RetentionAction) -> Self {
+        self.action = action;
+
+        self
+    }
+
+    /// Sets maximum number of rows, purged in a single transaction. Defaults to 1000.
+    #[must_use]
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+
+        self
+    }
+
+    /// Sets how long to wait between purges, when running in background. Defaults to 1 minute.
+    #[must_use]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+
+        self
+    }
+
+    #[must_use]
+    pub fn with_activeness_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.is_active = flag;
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+    use super::*;
+    use crate::outbox::{migrate, store};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Stores 4 events: 2 published long ago, 1 published recently and 1 unpublished.
+    async fn connect() -> SqlitePool {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&db).await.expect("Can't migrate");
+
+        for (key, published_at) in [
+            (0, Some("datetime('now', '-3 days')")),
+            (1, Some("datetime('now', '-2 days')")),
+            (2, Some("datetime('now')")),
+            (3, None),
+        ] {
+            store(&db, "panacea.test", Some(key), "payload", None)
+                .await
+                .expect("Can't store event");
+
+            if let Some(published_at) = published_at {
+                sqlx::query(&format!(
+                    "UPDATE panacea_outbox SET published_at = {published_at} WHERE key = '{key}'"
+                ))
+                .execute(&db)
+                .await
+                .expect("Can't publish event");
+            }
+        }
+
+        db
+    }
+
+    async fn keys(db: &SqlitePool, table: &str) -> Vec<String> {
+        sqlx::query_scalar(&format!("SELECT key FROM {table} ORDER BY sequence"))
+            .fetch_all(db)
+            .await
+            .expect("Can't fetch keys")
+    }
+
+    #[async_std::test]
+    async fn deletes_expired_published_rows() {
+        let db = connect().await;
+        let retention = Retention::new(db.clone()).with_retention_period(DAY);
+
+        assert_eq!(retention.purge().await.expect("Can't purge"), 2);
+        assert_eq!(keys(&db, "panacea_outbox").await, vec!["2", "3"]);
+        assert!(keys(&db, "panacea_outbox_archive").await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn archives_expired_published_rows() {
+        let db = connect().await;
+        sqlx::query(
+            "UPDATE panacea_outbox SET idempotency_key = 'user-0', event_type = 'UserCreated' WHERE key = '0'",
+        )
+        .execute(&db)
+        .await
+        .expect("Can't update row");
+        let retention = Retention::new(db.clone())
+            .with_retention_period(DAY)
+            .with_action(RetentionAction::Archive);
+
+        assert_eq!(retention.purge().await.expect("Can't purge"), 2);
+        assert_eq!(keys(&db, "panacea_outbox").await, vec!["2", "3"]);
+        assert_eq!(keys(&db, "panacea_outbox_archive").await, vec!["0", "1"]);
+
+        let archived: (Option<String>, Option<String>) = sqlx::query_as(
+            "SELECT idempotency_key, event_type FROM panacea_outbox_archive WHERE key = '0'",
+        )
+        .fetch_one(&db)
+        .await
+        .expect("Can't fetch archived row");
+        assert_eq!(
+            archived,
+            (Some("user-0".to_string()), Some("UserCreated".to_string()))
+        );
+    }
+
+    #[async_std::test]
+    async fn purges_dead_lettered_rows_and_dead_letters() {
+        let db = connect().await;
+        sqlx::query("UPDATE panacea_outbox SET last_error = 'oops', dead_lettered_at = datetime('now', '-3 days') WHERE key = '3'")
+            .execute(&db)
+            .await
+            .expect("Can't dead-letter row");
+        Outbox::default()
+            .dead_letter_row(&db, 4)
+            .await
+            .expect("Can't dead-letter row");
+        sqlx::query(
+            "UPDATE panacea_dead_letters SET dead_lettered_at = datetime('now', '-3 days')",
+        )
+        .execute(&db)
+        .await
+        .expect("Can't age dead letter");
+
+        // Dead letters are kept by default, so they can still be requeued
+        let retention = Retention::new(db.clone()).with_retention_period(DAY);
+        assert_eq!(retention.purge().await.expect("Can't purge"), 3);
+        assert_eq!(keys(&db, "panacea_outbox").await, vec!["2"]);
+        assert_eq!(keys(&db, "panacea_dead_letters").await, vec!["3"]);
+
+        let retention = retention.with_dead_letter_retention_period(DAY);
+        assert_eq!(retention.purge().await.expect("Can't purge"), 1);
+        assert!(keys(&db, "panacea_dead_letters").await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn archives_requeued_events_again() {
+        let db = connect().await;
+        let outbox = Outbox::default();
+        sqlx::query("UPDATE panacea_outbox SET last_error = 'oops', dead_lettered_at = datetime('now', '-3 days') WHERE key = '3'")
+            .execute(&db)
+            .await
+            .expect("Can't dead-letter row");
+        outbox
+            .dead_letter_row(&db, 4)
+            .await
+            .expect("Can't dead-letter row");
+        let retention = Retention::new(db.clone())
+            .with_retention_period(DAY)
+            .with_action(RetentionAction::Archive);
+        assert_eq!(retention.purge().await.expect("Can't purge"), 3);
+
+        // The requeued event is stored with its original id, and published again
+        assert!(outbox
+            .requeue_dead_letter(&db, 1)
+            .await
+            .expect("Can't requeue dead letter"));
+        sqlx::query(
+            "UPDATE panacea_outbox SET published_at = datetime('now', '-2 days') WHERE key = '3'",
+        )
+        .execute(&db)
+        .await
+        .expect("Can't publish event");
+
+        assert_eq!(retention.purge().await.expect("Can't purge"), 1);
+        assert_eq!(
+            keys(&db, "panacea_outbox_archive").await,
+            vec!["0", "1", "3"]
+        );
+        let published: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM panacea_outbox_archive WHERE key = '3' AND published_at IS NOT NULL AND dead_lettered_at IS NULL",
+        )
+        .fetch_one(&db)
+        .await
+        .expect("Can't count rows");
+        assert_eq!(published, 1);
+    }
+
+    #[async_std::test]
+    async fn purges_in_batches() {
+        let db = connect().await;
+        let retention = Retention::new(db.clone())
+            .with_retention_period(DAY)
+            .with_batch_size(1);
+
+        assert_eq!(retention.purge_batch().await.expect("Can't purge"), 1);
+        assert_eq!(keys(&db, "panacea_outbox").await, vec!["1", "2", "3"]);
+        assert_eq!(retention.purge_batch().await.expect("Can't purge"), 1);
+        assert_eq!(retention.purge_batch().await.expect("Can't purge"), 0);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{collections::VecDeque, time::Duration};
+
+use futures_util::{stream, Stream};
+use sqlx::{database::HasArguments, Executor, FromRow, IntoArguments, Pool};
+
+use panacea_types::event::Event;
+
+use super::{Dialect, Error, EventRow, Outbox};
+
+/// Number of rows, fetched from the outbox table at once.
+const PAGE_SIZE: u32 = 100;
+
+/// State of the stream between pages.
+struct Cursor<DB: sqlx::Database> {
+    pool: Pool<DB>,
+    outbox: Outbox,
+    /// Sequence of the last row, yielded by the stream.
+    after: i64,
+    rows: VecDeque<EventRow>,
+    /// Interval between polls for new rows, once the stream has caught up with the table.
+    poll_interval: Option<Duration>,
+    is_done: bool,
+}
+
+impl Outbox {
+    /// Streams events of the outbox table, starting from the row with given sequence, in the
+    /// order they were stored. The stream ends, once it has caught up with the table.
+    ///
+    /// Rows are fetched by pages, using keyset pagination on the sequence, so the stream can be
+    /// resumed from `sequence + 1` of the last yielded event. Only rows, that are still in the
+    /// outbox table, are streamed, so rows, that are removed by the relay, are missed. So are
+    /// rows of transactions, that commit after rows with greater sequences have been streamed.
+    ///
+    /// Yields an [`Error`] for rows, that can't be converted to events, and keeps going. An
+    /// error, that occurs when fetching rows, ends the stream.
+    pub fn stream<DB>(
+        &self,
+        pool: &Pool<DB>,
+        from_sequence: i64,
+    ) -> impl Stream<Item = Result<Event, Error>>
+    where
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        self.events(pool, from_sequence, None)
+    }
+
+    /// Streams events of the outbox table, starting from the row with given sequence, and
+    /// follows new rows as they arrive, polling the table every `poll_interval` once it has
+    /// caught up. The stream never ends. See [`Outbox::stream()`].
+    ///
+    /// Errors, that occur when fetching rows, are yielded, and fetching is retried after
+    /// `poll_interval`.
+    pub fn follow<DB>(
+        &self,
+        pool: &Pool<DB>,
+        from_sequence: i64,
+        poll_interval: Duration,
+    ) -> impl Stream<Item = Result<Event, Error>>
+    where
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        self.events(pool, from_sequence, Some(poll_interval))
+    }
+
+    fn events<DB>(
+        &self,
+        pool: &Pool<DB>,
+        from_sequence: i64,
+        poll_interval: Option<Duration>,
+    ) -> impl Stream<Item = Result<Event, Error>>
+    where
+        DB: Dialect,
+        for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+        for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+        for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+        for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+    {
+        let cursor = Cursor {
+            pool: pool.clone(),
+            outbox: self.clone(),
+            after: from_sequence.saturating_sub(1),
+            rows: VecDeque::new(),
+            poll_interval,
+            is_done: false,
+        };
+
+        stream::unfold(cursor, |mut cursor| async move {
+            loop {
+                if cursor.is_done {
+                    return None;
+                }
+
+                if let Some(row) = cursor.rows.pop_front() {
+                    cursor.after = row.sequence;
+                    let event = cursor
+                        .outbox
+                        .event_from_row(row)
+                        .map_err(Error::EventDecoding);
+
+                    return Some((event, cursor));
+                }
+
+                match cursor
+                    .outbox
+                    .rows_after(&cursor.pool, cursor.after, PAGE_SIZE)
+                    .await
+                {
+                    Ok(rows) if rows.is_empty() => match cursor.poll_interval {
+                        Some(poll_interval) => async_std::task::sleep(poll_interval).await,
+                        None => return None,
+                    },
+                    Ok(rows) => cursor.rows = rows.into(),
+                    Err(err) => {
+                        match cursor.poll_interval {
+                            Some(poll_interval) => async_std::task::sleep(poll_interval).await,
+                            None => cursor.is_done = true,
+                        }
+
+                        return Some((Err(err), cursor));
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Streams events of the default outbox table, starting from the row with given sequence. See
+/// [`Outbox::stream()`].
+pub fn stream<DB>(pool: &Pool<DB>, from_sequence: i64) -> impl Stream<Item = Result<Event, Error>>
+where
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+{
+    Outbox::default().stream(pool, from_sequence)
+}
+
+/// Streams events of the default outbox table, starting from the row with given sequence, and
+/// follows new rows as they arrive. See [`Outbox::follow()`].
+pub fn follow<DB>(
+    pool: &Pool<DB>,
+    from_sequence: i64,
+    poll_interval: Duration,
+) -> impl Stream<Item = Result<Event, Error>>
+where
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'r> EventRow: FromRow<'r, <DB as sqlx::Database>::Row>,
+    for<'q> i64: sqlx::Type<DB> + sqlx::Encode<'q, DB>,
+{
+    Outbox::default().follow(pool, from_sequence, poll_interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+
+    use super::*;
+    use crate::outbox::{migrate, store_events};
+    use panacea_types::event;
+
+    async fn connect() -> SqlitePool {
+        // A single connection, so every query sees the same in-memory database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&pool).await.expect("Can't migrate");
+
+        pool
+    }
+
+    async fn store(pool: &SqlitePool, count: usize) -> Vec<Event> {
+        let events: Vec<Event> = (0..count)
+            .map(|i| event::new(&"panacea.test", Some(i), &"payload", None))
+            .collect();
+        store_events(pool, &events)
+            .await
+            .expect("Can't store events");
+
+        events
+    }
+
+    #[async_std::test]
+    async fn streams_events_by_pages_from_sequence() {
+        let pool = connect().await;
+        let events = store(&pool, PAGE_SIZE as usize + 1).await;
+
+        let streamed: Vec<Event> = stream(&pool, 0)
+            .map(|event| event.expect("Can't stream event"))
+            .collect()
+            .await;
+        let ids: Vec<_> = streamed.iter().map(|event| event.id).collect();
+        assert_eq!(ids, events.iter().map(|event| event.id).collect::<Vec<_>>());
+
+        let sequence = streamed[10].sequence.expect("Event has no sequence");
+        let resumed: Vec<_> = stream(&pool, sequence)
+            .map(|event| event.expect("Can't stream event").id)
+            .collect()
+            .await;
+        assert_eq!(resumed, ids[10..]);
+    }
+
+    #[async_std::test]
+    async fn follows_new_rows() {
+        let pool = connect().await;
+        let events = store(&pool, 1).await;
+        let mut following = Box::pin(follow(&pool, 0, Duration::from_millis(10)));
+
+        let first = following.next().await.expect("Stream has ended");
+        assert_eq!(first.expect("Can't stream event").id, events[0].id);
+
+        let events = store(&pool, 1).await;
+        let next = following.next().await.expect("Stream has ended");
+        assert_eq!(next.expect("Can't stream event").id, events[0].id);
+    }
+}
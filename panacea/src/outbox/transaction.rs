@@ -0,0 +1,209 @@
+use std::ops::{Deref, DerefMut};
+
+use chrono::{DateTime, Utc};
+use sqlx::{database::HasArguments, Acquire, Encode, Executor, IntoArguments, Transaction, Type};
+use uuid::Uuid;
+
+use panacea_types::event::Event;
+
+use super::{Dialect, Error, Outbox};
+
+/// Database transaction, that buffers emitted events and stores them to the outbox table right
+/// before it's committed.
+///
+/// Events are stored with a single batched insert (see [`Outbox::store_events()`]), and are
+/// dropped along with the transaction, if it's rolled back or not committed. Dereferences to
+/// the underlying connection, so it can be used as an executor with `&mut *tx`, the same way as
+/// [`sqlx::Transaction`].
+///
+/// Within worker handlers, begin it from the handler's transaction, so events are stored
+/// (within a savepoint) only if the whole handling succeeds.
+pub struct OutboxTransaction<'c, DB: sqlx::Database> {
+    tx: Transaction<'c, DB>,
+    outbox: Outbox,
+    events: Vec<Event>,
+}
+
+impl<'c, DB> OutboxTransaction<'c, DB>
+where
+    DB: Dialect,
+    for<'t> &'t mut <DB as sqlx::Database>::Connection: Acquire<'t, Database = DB>,
+    for<'t> &'t mut <DB as sqlx::Database>::Connection: Executor<'t, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
+{
+    /// Begins transaction, that stores events to the default outbox table.
+    ///
+    /// Shorthand for [`Outbox::begin()`] with default [`Outbox`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the transaction can't be started.
+    pub async fn begin<A>(executor: A) -> Result<Self, Error>
+    where
+        A: Acquire<'c, Database = DB>,
+    {
+        Outbox::default().begin(executor).await
+    }
+
+    /// Buffers event to be stored to the outbox table on commit.
+    pub fn emit(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Returns events, emitted so far.
+    #[must_use]
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Stores emitted events to the outbox table and commits the transaction.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if events can't be stored, or if the transaction can't be
+    /// committed. The transaction is rolled back then.
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.outbox
+            .store_events(&mut *self.tx, &self.events)
+            .await?;
+        self.tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Rolls back the transaction, dropping emitted events.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the transaction can't be rolled back.
+    pub async fn rollback(self) -> Result<(), Error> {
+        self.tx.rollback().await?;
+
+        Ok(())
+    }
+}
+
+impl<'c, DB: sqlx::Database> Deref for OutboxTransaction<'c, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl<'c, DB: sqlx::Database> DerefMut for OutboxTransaction<'c, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+impl Outbox {
+    /// Begins transaction, that stores emitted events to this outbox table on commit. See
+    /// [`OutboxTransaction`].
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error`] if the transaction can't be started.
+    pub async fn begin<'c, A, DB>(&self, executor: A) -> Result<OutboxTransaction<'c, DB>, Error>
+    where
+        A: Acquire<'c, Database = DB>,
+        DB: Dialect,
+    {
+        Ok(OutboxTransaction {
+            tx: executor.begin().await?,
+            outbox: self.clone(),
+            events: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{Connection, SqliteConnection};
+
+    use super::*;
+    use crate::outbox::migrate;
+    use panacea_types::event;
+
+    async fn connect() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:")
+            .await
+            .expect("Can't connect to SQLite");
+
+        migrate(&mut conn).await.expect("Can't migrate");
+
+        conn
+    }
+
+    async fn count(conn: &mut SqliteConnection) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM panacea_outbox")
+            .fetch_one(conn)
+            .await
+            .expect("Can't count events")
+    }
+
+    fn user_created() -> Event {
+        event::new(&"user.created", Some("user"), &"payload", None)
+    }
+
+    #[async_std::test]
+    async fn stores_emitted_events_on_commit() {
+        let mut conn = connect().await;
+
+        let mut tx = OutboxTransaction::begin(&mut conn)
+            .await
+            .expect("Can't begin transaction");
+        tx.emit(user_created());
+        tx.emit(user_created());
+        sqlx::query("SELECT 1")
+            .execute(&mut *tx)
+            .await
+            .expect("Can't execute query");
+        tx.commit().await.expect("Can't commit transaction");
+
+        assert_eq!(count(&mut conn).await, 2);
+    }
+
+    #[async_std::test]
+    async fn drops_emitted_events_on_rollback() {
+        let mut conn = connect().await;
+
+        let mut tx = OutboxTransaction::begin(&mut conn)
+            .await
+            .expect("Can't begin transaction");
+        tx.emit(user_created());
+        tx.rollback().await.expect("Can't rollback transaction");
+
+        let mut tx = OutboxTransaction::begin(&mut conn)
+            .await
+            .expect("Can't begin transaction");
+        tx.emit(user_created());
+        drop(tx);
+
+        assert_eq!(count(&mut conn).await, 0);
+    }
+
+    #[async_std::test]
+    async fn nests_into_outer_transaction() {
+        let mut conn = connect().await;
+        let mut outer = Connection::begin(&mut conn)
+            .await
+            .expect("Can't begin transaction");
+
+        let mut tx = Outbox::default()
+            .begin(&mut outer)
+            .await
+            .expect("Can't begin transaction");
+        tx.emit(user_created());
+        tx.commit().await.expect("Can't commit transaction");
+
+        // Events are stored only if the outer transaction (e.g. of a worker handler) commits
+        outer.rollback().await.expect("Can't rollback transaction");
+        assert_eq!(count(&mut conn).await, 0);
+    }
+}
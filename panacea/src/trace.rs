@@ -0,0 +1,127 @@
+//! Propagation of [W3C trace context](https://www.w3.org/TR/trace-context/) through event
+//! headers, so a distributed trace runs from the code, that stores an event, through the outbox
+//! and the broker, to the handlers of the event.
+//!
+//! Trace context is taken from the current [`tracing`] span, so spans have to be recorded by
+//! the [`tracing_opentelemetry`] layer to be propagated.
+
+use opentelemetry::{
+    propagation::TextMapPropagator,
+    trace::{TraceContextExt, TraceId},
+    Context,
+};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use panacea_types::event::{Event, Headers};
+
+pub use panacea_types::event::{TRACEPARENT_HEADER, TRACESTATE_HEADER};
+
+/// Injects trace context of the current span into headers. Headers, that already carry trace
+/// context (e.g. of a requeued event), are left as is, as well as headers of events, that are
+/// stored outside of any recorded span.
+pub fn inject(headers: &mut Headers) {
+    if headers.contains_key(TRACEPARENT_HEADER) {
+        return;
+    }
+
+    let context = Span::current().context();
+    if context.span().span_context().is_valid() {
+        TraceContextPropagator::new().inject_context(&context, headers);
+    }
+}
+
+/// Extracts trace context from headers. Returns empty context, if headers carry no (or
+/// malformed) trace context.
+#[must_use]
+pub fn extract(headers: &Headers) -> Context {
+    TraceContextPropagator::new().extract(headers)
+}
+
+/// Returns id of the trace, the event belongs to, if its headers carry trace context.
+#[must_use]
+pub fn trace_id(event: &Event) -> Option<TraceId> {
+    let context = extract(&event.headers);
+    let span_context = context.span().span_context().clone();
+
+    span_context.is_valid().then(|| span_context.trace_id())
+}
+
+/// Opens span for handling of the event, that is a child of the span, the event was stored
+/// within.
+pub(crate) fn handling_span(event: &Event) -> Span {
+    let span = tracing::info_span!(
+        "handle event",
+        otel.name = %format!("{} process", event.topic),
+        otel.kind = "consumer",
+        event.id = %event.id,
+        event.topic = %event.topic,
+    );
+    span.set_parent(extract(&event.headers));
+
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+    use super::*;
+    use panacea_types::event;
+
+    fn with_tracer<T>(f: impl FnOnce() -> T) -> T {
+        // Tracer refers to the provider weakly, so it has to outlive the subscriber
+        let provider = TracerProvider::default();
+        let tracer = provider.tracer("panacea");
+        let subscriber =
+            Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, f)
+    }
+
+    fn user_created() -> Event {
+        event::new(&"user.created", Some("user"), &"payload", None)
+    }
+
+    #[test]
+    fn propagates_trace_to_handling_span() {
+        with_tracer(|| {
+            let span = tracing::info_span!("request");
+            let mut event = user_created();
+            span.in_scope(|| inject(&mut event.headers));
+
+            let trace_id = span.context().span().span_context().trace_id();
+            assert!(event.headers[TRACEPARENT_HEADER].contains(&trace_id.to_string()));
+            assert_eq!(super::trace_id(&event), Some(trace_id));
+
+            let handling = handling_span(&event)
+                .context()
+                .span()
+                .span_context()
+                .clone();
+            assert_eq!(handling.trace_id(), trace_id);
+            assert_ne!(
+                handling.span_id(),
+                span.context().span().span_context().span_id()
+            );
+        });
+    }
+
+    #[test]
+    fn keeps_headers_without_recorded_span_or_with_trace_context() {
+        let mut event = user_created();
+        inject(&mut event.headers);
+        assert!(!event.headers.contains_key(TRACEPARENT_HEADER));
+        assert_eq!(trace_id(&event), None);
+
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        event
+            .headers
+            .insert(TRACEPARENT_HEADER.to_string(), traceparent.to_string());
+        with_tracer(|| tracing::info_span!("request").in_scope(|| inject(&mut event.headers)));
+        assert_eq!(event.headers[TRACEPARENT_HEADER], traceparent);
+    }
+}
@@ -1,36 +1,68 @@
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
 };
 
-use crate::outbox;
+use crate::outbox::{self, Dialect};
 use async_std::sync::Mutex;
-use panacea_types::{event::Event, handler::MaybeHandlers, state::State, worker::EventSource};
+use chrono::{DateTime, Utc};
+use panacea_types::{
+    event::Event,
+    handler::MaybeHandlers,
+    state::State,
+    worker::{EventSource, SkipReason},
+};
+use sqlx::{
+    database::HasArguments, ColumnIndex, Decode, Encode, Executor, IntoArguments, Pool, Type,
+};
 use state::Container;
+use uuid::Uuid;
+
+/// Maximum number of events, failed attempts to handle which are tracked. Once it's reached, the
+/// event, that has failed least recently, is forgotten (e.g. as it's not redelivered anymore).
+const MAX_TRACKED_EVENTS: usize = 10_000;
 
-pub struct Worker<S: EventSource> {
+/// Function, that resolves [`panacea_types::Handler`]'s from given [`Event`].
+type HandlersResolver<DB> = Box<dyn Fn(&Event) -> MaybeHandlers<DB> + Send>;
+
+pub struct Worker<S: EventSource, DB: Dialect> {
     /// [`EventSource`] instance.
     event_source: Arc<Mutex<S>>,
     /// Function, that resolves [`panacea_types::Handler`]'s from given [`Event`].
-    handlers_resolver: Box<dyn Fn(&Event) -> MaybeHandlers + Send>,
-    /// Holds sqlx PostgreSQL connection pool.
-    #[cfg(feature = "postgres")]
-    db: Option<sqlx::PgPool>,
-    /// Holds sqlx MySQL connection pool.
-    #[cfg(feature = "mysql")]
-    db: Option<sqlx::MySqlPool>,
-    /// Holds sqlx SQLite connection pool.
-    #[cfg(feature = "sqlite")]
-    db: Option<sqlx::SqlitePool>,
+    handlers_resolver: HandlersResolver<DB>,
+    /// Holds sqlx connection pool.
+    db: Option<Pool<DB>>,
+    /// Outbox table, events returned by handlers are stored to.
+    outbox: outbox::Outbox,
+    /// Number of failed attempts to handle an event, after which it's stored as a dead letter.
+    max_attempts: Option<u32>,
+    /// Failed attempts to handle events, that are not dead-lettered yet, along with time of the
+    /// last one, by event id.
+    attempts: HashMap<Uuid, (u32, Instant)>,
     /// Worker activeness flag.
     is_active: Arc<AtomicBool>,
     /// Managed state.
     pub state: Container![Sync + Send],
 }
 
-impl<S> Worker<S>
+impl<S, DB> Worker<S, DB>
 where
     S: EventSource + 'static,
+    DB: Dialect,
+    for<'c> &'c mut <DB as sqlx::Database>::Connection: Executor<'c, Database = DB>,
+    for<'q> <DB as HasArguments<'q>>::Arguments: IntoArguments<'q, DB>,
+    for<'q> String: Type<DB> + Encode<'q, DB>,
+    for<'q> Vec<u8>: Type<DB> + Encode<'q, DB>,
+    for<'q> Uuid: Type<DB> + Encode<'q, DB> + Decode<'q, DB>,
+    usize: ColumnIndex<<DB as sqlx::Database>::Row>,
+    for<'q> Option<String>: Type<DB> + Encode<'q, DB>,
+    for<'q> i64: Type<DB> + Encode<'q, DB>,
+    for<'q> DateTime<Utc>: Type<DB> + Encode<'q, DB>,
+    for<'q> Option<DateTime<Utc>>: Type<DB> + Encode<'q, DB>,
 {
     pub fn new(event_source: S) -> Self {
         Self {
@@ -39,12 +71,10 @@ where
                 println!("Warning: using default handler name resolver");
                 None
             }),
-            #[cfg(feature = "postgres")]
-            db: None,
-            #[cfg(feature = "mysql")]
-            db: None,
-            #[cfg(feature = "sqlite")]
             db: None,
+            outbox: outbox::Outbox::default(),
+            max_attempts: None,
+            attempts: HashMap::new(),
             is_active: Arc::new(AtomicBool::new(true)),
             state: <Container![Send + Sync]>::new(),
         }
@@ -55,44 +85,115 @@ where
 
         'outer: while self.is_active.load(Ordering::SeqCst) {
             let mut es_lock = self.event_source.lock().await;
-            let Some(event) = es_lock.next().await else {
-                continue
+            // Event is cloned, so the source stays available for reporting handling results.
+            let Some(event) = es_lock.next().await.cloned() else {
+                continue;
             };
             println!("{event:?}");
 
+            // Decrypt and decompress payload, if the event comes right from the outbox
+            let opened = match self.outbox.open_event(event.clone()) {
+                Ok(opened) => opened,
+                Err(err) => {
+                    let error = outbox::error_chain(&err);
+                    eprintln!("Can't open event {}: {error}", event.id);
+
+                    // Event is dead-lettered as is, so it can be opened once the cause is fixed
+                    // (e.g. the missing key is configured)
+                    if Self::fail(
+                        &self.outbox,
+                        self.db.as_ref(),
+                        self.max_attempts,
+                        &mut self.attempts,
+                        &event,
+                        None,
+                        &error,
+                    )
+                    .await
+                    {
+                        es_lock.skipped_with_reason(&event, SkipReason::DeadLettered);
+                    } else {
+                        es_lock.failed(&event);
+                    }
+                    continue;
+                }
+            };
+
+            // Discard stale event
+            if opened.is_expired_at(Utc::now()) {
+                println!("Skipping event {}: {}", event.id, SkipReason::Expired);
+                es_lock.skipped_with_reason(&event, SkipReason::Expired);
+                continue;
+            }
+
             // Resolve handlers
-            let Some(handlers) = (self.handlers_resolver)(event) else {
-                self.event_source.lock().await.skipped(event);
+            let Some(handlers) = (self.handlers_resolver)(&opened) else {
+                es_lock.skipped_with_reason(&event, SkipReason::NoHandlers);
                 continue;
             };
 
             // Handle event
-            #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
             if let Some(db) = &self.db {
                 // `ALL` handlers processing (tries to handle event with all handlers, fails if any of them fails)
                 //
                 // TODO: implement `ANY` handlers processing (processes event with all handlers,
                 // even if some of them fails).
 
+                // Continue trace of the event, so events stored by handlers belong to it as well
+                #[cfg(feature = "tracing")]
+                let span = crate::trace::handling_span(&opened);
+
                 // Begin transaction
                 let mut tx = db.begin().await.expect("Can't begin transaction");
 
                 // Handle event
                 for handler in handlers {
-                    match handler.handle(&mut self.state, &mut tx, event).await {
+                    let handling = handler.handle(&mut self.state, &mut tx, &opened);
+                    #[cfg(feature = "tracing")]
+                    let handling = tracing::Instrument::instrument(handling, span.clone());
+
+                    match handling.await {
                         // Everything is ok, got some events back
                         Ok(Some(events)) => {
                             for event in events {
-                                outbox::store_event(&mut tx, event)
-                                    .await
-                                    .expect("Can't store event");
+                                let event = event.caused_by(&opened);
+                                let storing = self.outbox.insert_event(&mut tx, event);
+                                #[cfg(feature = "tracing")]
+                                let storing =
+                                    tracing::Instrument::instrument(storing, span.clone());
+
+                                storing.await.expect("Can't store event");
                             }
                         }
                         // Everything is ok, no events
                         Ok(None) => {}
                         // Something went wrong
-                        Err(_) => {
-                            self.event_source.lock().await.failed(event);
+                        Err(err) => {
+                            // Roll back before storing a dead letter, as it needs a connection
+                            drop(tx);
+
+                            let error = outbox::error_chain(&err);
+                            eprintln!(
+                                "Can't handle event {} with {}: {error}",
+                                event.id,
+                                handler.name()
+                            );
+
+                            if Self::fail(
+                                &self.outbox,
+                                Some(db),
+                                self.max_attempts,
+                                &mut self.attempts,
+                                &opened,
+                                Some(handler.name()),
+                                &error,
+                            )
+                            .await
+                            {
+                                es_lock.skipped_with_reason(&event, SkipReason::DeadLettered);
+                            } else {
+                                es_lock.failed(&event);
+                            }
                             continue 'outer;
                         }
                     };
@@ -100,10 +201,57 @@ where
 
                 // Commit transaction
                 tx.commit().await.expect("Can't commit transaction");
+                self.attempts.remove(&event.id);
             }
 
             // Handle succeeded
-            self.event_source.lock().await.succeeded(event);
+            es_lock.succeeded(&event);
+        }
+    }
+
+    /// Counts failed attempt to handle the event, and stores it as a dead letter, once attempts
+    /// are exhausted. Attempts are counted only if failed events are dead-lettered, and only of
+    /// events, that can be told apart by id. Returns whether the event was dead-lettered.
+    /// Doesn't borrow the worker, so its future stays `Send`.
+    async fn fail(
+        outbox: &outbox::Outbox,
+        db: Option<&Pool<DB>>,
+        max_attempts: Option<u32>,
+        attempts: &mut HashMap<Uuid, (u32, Instant)>,
+        event: &Event,
+        handler: Option<&str>,
+        error: &str,
+    ) -> bool {
+        let (Some(db), Some(max_attempts)) = (db, max_attempts) else {
+            return false;
+        };
+        if event.id.is_nil() {
+            return false;
+        }
+
+        let count = count_attempt(attempts, event.id);
+        if count < max_attempts {
+            return false;
+        }
+
+        match outbox
+            .store_dead_letter(db, event, handler, error, count)
+            .await
+        {
+            Ok(()) => {
+                println!(
+                    "Dead-lettering event {} after {count} failed attempts",
+                    event.id
+                );
+                attempts.remove(&event.id);
+
+                true
+            }
+            Err(err) => {
+                eprintln!("Can't store dead letter of event {}: {err}", event.id);
+
+                false
+            }
         }
     }
 
@@ -112,36 +260,41 @@ where
     #[must_use]
     pub fn with_handlers_resolver<F>(mut self, resolver: F) -> Self
     where
-        F: Fn(&Event) -> MaybeHandlers + Send + 'static,
+        F: Fn(&Event) -> MaybeHandlers<DB> + Send + 'static,
     {
         self.handlers_resolver = Box::new(resolver);
 
         self
     }
 
-    /// Sets [`sqlx::MySqlPool`] to the [`Worker`].
-    #[cfg(feature = "mysql")]
+    /// Sets sqlx connection pool to the [`Worker`].
     #[must_use]
-    pub fn with_db(mut self, db: sqlx::MySqlPool) -> Self {
+    pub fn with_db(mut self, db: Pool<DB>) -> Self {
         self.db = Some(db);
 
         self
     }
 
-    /// Sets [`sqlx::PgPool`] to the [`Worker`].
-    #[cfg(feature = "postgres")]
+    /// Sets outbox table, events returned by handlers are stored to.
+    /// Defaults to [`outbox::Outbox::default`].
+    ///
+    /// Encrypted and compressed events are opened with [`outbox::Outbox::open_event`] before
+    /// handling, so the outbox should have the same keys as producers of the events.
     #[must_use]
-    pub fn with_db(mut self, db: sqlx::PgPool) -> Self {
-        self.db = Some(db);
+    pub fn with_outbox(mut self, outbox: outbox::Outbox) -> Self {
+        self.outbox = outbox;
 
         self
     }
 
-    /// Sets [`sqlx::SqlitePool`] to the [`Worker`].
-    #[cfg(feature = "sqlite")]
+    /// Stores events as dead letters (see [`outbox::DeadLetter`]) after given number of failed
+    /// attempts to handle them, and skips them instead of reporting as failed. Attempts are
+    /// counted in memory, so they start over when the worker is restarted. Requires database
+    /// connection pool (see [`Self::with_db`]). Events are reported as failed every time by
+    /// default.
     #[must_use]
-    pub fn with_db(mut self, db: sqlx::SqlitePool) -> Self {
-        self.db = Some(db);
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
 
         self
     }
@@ -173,6 +326,26 @@ where
     }
 }
 
+/// Counts failed attempt to handle the event, returning number of its failed attempts so far.
+/// Forgets the least recently failed event, if [`MAX_TRACKED_EVENTS`] are already tracked.
+fn count_attempt(attempts: &mut HashMap<Uuid, (u32, Instant)>, id: Uuid) -> u32 {
+    if attempts.len() >= MAX_TRACKED_EVENTS && !attempts.contains_key(&id) {
+        let stale = attempts
+            .iter()
+            .min_by_key(|(_, (_, failed_at))| *failed_at)
+            .map(|(id, _)| *id);
+        if let Some(stale) = stale {
+            attempts.remove(&stale);
+        }
+    }
+
+    let (count, failed_at) = attempts.entry(id).or_insert((0, Instant::now()));
+    *count += 1;
+    *failed_at = Instant::now();
+
+    *count
+}
+
 #[cfg(test)]
 mod tests {
     use async_std::task;
@@ -209,7 +382,7 @@ mod tests {
         T: EventSource + std::marker::Sync + std::marker::Send + 'static,
     {
         let is_active = Arc::new(AtomicBool::new(true));
-        let worker = Worker::new(es).with_activeness_flag(is_active.clone());
+        let worker = Worker::<_, sqlx::Sqlite>::new(es).with_activeness_flag(is_active.clone());
         task::spawn(async move {
             std::thread::sleep(time::Duration::from_millis(10));
             is_active.store(false, Ordering::SeqCst);
@@ -248,6 +421,22 @@ mod tests {
             current_event: None,
         };
 
-        let _worker = Worker::new(col).with_handlers_resolver(|_| handlers![handle_some_stuff]);
+        let _worker = Worker::<_, sqlx::Sqlite>::new(col)
+            .with_handlers_resolver(|_| handlers![handle_some_stuff]);
+    }
+
+    #[test]
+    fn forgets_least_recently_failed_events() {
+        let mut attempts = HashMap::new();
+        let first = Uuid::now_v7();
+        assert_eq!(count_attempt(&mut attempts, first), 1);
+        for _ in 1..MAX_TRACKED_EVENTS {
+            count_attempt(&mut attempts, Uuid::now_v7());
+        }
+        assert_eq!(attempts.len(), MAX_TRACKED_EVENTS);
+
+        count_attempt(&mut attempts, Uuid::now_v7());
+        assert_eq!(attempts.len(), MAX_TRACKED_EVENTS);
+        assert!(!attempts.contains_key(&first));
     }
 }
@@ -0,0 +1,883 @@
+//! End-to-end scenarios, that run against every backend.
+//!
+//! SQLite runs in memory. PostgreSQL is expected at `PANACEA_POSTGRES_URL` (defaults to
+//! `postgres://postgres@localhost/postgres`), and its scenarios are skipped when it's not
+//! available.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::{StreamExt, TryStreamExt};
+use sqlx::{postgres::PgPoolOptions, sqlite::SqlitePoolOptions, PgPool, SqlitePool};
+use uuid::Uuid;
+
+use panacea::{
+    handler, handlers,
+    outbox::{
+        Backoff, Columns, Compression, DeadLetterOrigin, Encoding, Encryption, Outbox, Relay,
+        Retention, RetentionAction, StaticKeys,
+    },
+    worker::Worker,
+};
+use panacea_types::{
+    event::{self, ID_HEADER},
+    handler::HandlingResult,
+    publisher::{self, InMemoryPublisher, Publisher},
+    worker::SkipReason,
+    Event, EventSource,
+};
+
+async fn connect_sqlite() -> Option<SqlitePool> {
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Can't connect to SQLite");
+
+    Some(db)
+}
+
+async fn connect_postgres() -> Option<PgPool> {
+    let url = std::env::var("PANACEA_POSTGRES_URL")
+        .unwrap_or_else(|_| "postgres://postgres@localhost/postgres".to_string());
+
+    match PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(1))
+        .connect(&url)
+        .await
+    {
+        Ok(db) => Some(db),
+        Err(err) => {
+            eprintln!("Skipping PostgreSQL scenario, can't connect to {url}: {err}");
+            None
+        }
+    }
+}
+
+/// Feeds events from a queue, deactivating the worker once the queue is drained.
+struct QueueSource {
+    events: VecDeque<Event>,
+    current: Option<Event>,
+    is_active: Arc<AtomicBool>,
+    skip_reasons: Arc<Mutex<Vec<SkipReason>>>,
+}
+
+#[async_trait]
+impl EventSource for QueueSource {
+    async fn next(&mut self) -> Option<&Event> {
+        if self.current.is_none() {
+            self.current = self.events.pop_front();
+        }
+
+        if self.current.is_none() {
+            self.is_active.store(false, Ordering::SeqCst);
+        }
+
+        self.current.as_ref()
+    }
+
+    fn succeeded(&mut self, _event: &Event) {
+        self.current = None;
+    }
+
+    fn failed(&mut self, _event: &Event) {
+        self.current = None;
+    }
+
+    fn skipped(&mut self, _event: &Event) {
+        self.current = None;
+    }
+
+    fn skipped_with_reason(&mut self, event: &Event, reason: SkipReason) {
+        self.skip_reasons
+            .lock()
+            .expect("Can't lock skip reasons")
+            .push(reason);
+        self.skipped(event);
+    }
+}
+
+/// Rejects events of the `user.banned` topic, publishing other ones in memory.
+#[derive(Clone, Default)]
+struct RejectingPublisher {
+    inner: InMemoryPublisher,
+}
+
+#[async_trait]
+impl Publisher for RejectingPublisher {
+    async fn publish(&self, event: &Event) -> Result<(), publisher::Error> {
+        if event.topic == "user.banned" {
+            return Err(anyhow!("topic is forbidden").into());
+        }
+
+        self.inner.publish(event).await
+    }
+}
+
+#[handler]
+fn send_greeting() -> HandlingResult {
+    Ok(Some(vec![event::new(
+        &"greeting.sent",
+        Some("user"),
+        &"Hello!",
+        None,
+    )]))
+}
+
+#[handler]
+fn reject() -> HandlingResult {
+    Err(anyhow!("user is banned").into())
+}
+
+fn user_created() -> Event {
+    event::new(&"user.created", Some("user"), &"{}", None)
+}
+
+fn user_banned() -> Event {
+    event::new(&"user.banned", Some("user"), &"{}", None)
+}
+
+/// Defines the same scenarios for a backend, with `$connect` returning `None` when the backend
+/// is not available. Every scenario uses its own outbox table, so they can run in parallel.
+macro_rules! scenarios {
+    ($backend:ident, $pool:ty, $connect:ident) => {
+        mod $backend {
+            use super::*;
+
+            /// Connects to the backend and creates a fresh outbox table for the scenario.
+            async fn setup(scenario: &str) -> Option<($pool, Outbox)> {
+                setup_with(scenario, Outbox::new()).await
+            }
+
+            /// Same as `setup`, but with given outbox configuration.
+            async fn setup_with(scenario: &str, outbox: Outbox) -> Option<($pool, Outbox)> {
+                let db = $connect().await?;
+                let table = format!("panacea_{}_{scenario}", stringify!($backend));
+                let outbox = outbox.with_table(&table);
+
+                for suffix in ["", "_archive", "_dead_letters", "_migrations"] {
+                    sqlx::query(&format!("DROP TABLE IF EXISTS {table}{suffix}"))
+                        .execute(&db)
+                        .await
+                        .expect("Can't drop table");
+                }
+                outbox.migrate(&db).await.expect("Can't migrate");
+
+                Some((db, outbox))
+            }
+
+            async fn count(db: &$pool, table: &str, condition: &str) -> i64 {
+                sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table} WHERE {condition}"))
+                    .fetch_one(db)
+                    .await
+                    .expect("Can't count events")
+            }
+
+            /// Runs worker until all events are consumed, returning reasons of skipped events.
+            async fn run_worker(
+                db: &$pool,
+                outbox: &Outbox,
+                events: Vec<Event>,
+            ) -> Vec<SkipReason> {
+                let is_active = Arc::new(AtomicBool::new(true));
+                let skip_reasons = Arc::new(Mutex::new(Vec::new()));
+                let source = QueueSource {
+                    events: events.into(),
+                    current: None,
+                    is_active: is_active.clone(),
+                    skip_reasons: skip_reasons.clone(),
+                };
+
+                Worker::new(source)
+                    .with_db(db.clone())
+                    .with_outbox(outbox.clone())
+                    .with_max_attempts(2)
+                    .with_activeness_flag(is_active)
+                    .with_handlers_resolver(|event| match event.topic.as_str() {
+                        "user.created" => handlers![send_greeting],
+                        "user.banned" => handlers![send_greeting, reject],
+                        _ => None,
+                    })
+                    .run()
+                    .await;
+
+                let skip_reasons = skip_reasons.lock().expect("Can't lock skip reasons");
+                skip_reasons.clone()
+            }
+
+            #[async_std::test]
+            async fn stores_and_relays_events() {
+                let Some((db, outbox)) = setup("relays").await else {
+                    return;
+                };
+                let mut headers = event::Headers::new();
+                headers.insert("answer".to_string(), "42".to_string());
+
+                for key in 0..3 {
+                    outbox
+                        .store(
+                            &db,
+                            "panacea.test",
+                            Some(key),
+                            "payload",
+                            Some(headers.clone()),
+                        )
+                        .await
+                        .expect("Can't store event");
+                }
+
+                let publisher = InMemoryPublisher::new();
+                let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(outbox.clone());
+
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 3);
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+
+                let events = publisher.events();
+                let keys: Vec<_> = events.iter().map(|event| event.key.as_deref()).collect();
+                assert_eq!(keys, vec![Some("0"), Some("1"), Some("2")]);
+                for event in &events {
+                    assert_eq!(event.headers.get("answer"), Some(&"42".to_string()));
+                    assert_eq!(event.headers.get(ID_HEADER), Some(&event.id.to_string()));
+                    assert_eq!(event.payload, b"payload");
+                }
+                assert_eq!(
+                    count(&db, outbox.table(), "published_at IS NOT NULL").await,
+                    3
+                );
+            }
+
+            #[async_std::test]
+            async fn relays_encrypted_events_after_key_rotation() {
+                let Some((db, outbox)) = setup("encryption").await else {
+                    return;
+                };
+                let payload = "Jane Doe, jane@example.com; ".repeat(10);
+
+                let producer = outbox
+                    .clone()
+                    .with_compression(Compression::new(Encoding::Gzip))
+                    .with_encryption(Encryption::new(StaticKeys::new("old", [1; 32])));
+                producer
+                    .store(&db, "user.created", Some("user"), payload.as_str(), None)
+                    .await
+                    .expect("Can't store event");
+                let stored: Vec<u8> =
+                    sqlx::query_scalar(&format!("SELECT payload FROM {}", outbox.table()))
+                        .fetch_one(&db)
+                        .await
+                        .expect("Can't fetch payload");
+                assert!(!String::from_utf8_lossy(&stored).contains("Jane"));
+
+                let rotated = outbox.with_encryption(Encryption::new(
+                    StaticKeys::new("new", [2; 32]).with_key("old", [1; 32]),
+                ));
+                let publisher = InMemoryPublisher::new();
+                let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(rotated);
+
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 1);
+
+                let events = publisher.events();
+                assert_eq!(events[0].payload, payload.as_bytes());
+                assert_eq!(events[0].headers.len(), 1);
+            }
+
+            #[async_std::test]
+            async fn holds_scheduled_events_until_delivery_time() {
+                let Some((db, outbox)) = setup("scheduled").await else {
+                    return;
+                };
+                let now = Utc::now();
+                let events = vec![
+                    event::new(&"reminder.due", Some("later"), &"{}", None)
+                        .with_deliver_at(now + chrono::Duration::hours(24)),
+                    event::new(&"reminder.due", Some("now"), &"{}", None)
+                        .with_deliver_at(now - chrono::Duration::seconds(1)),
+                    event::new(&"user.created", Some("user"), &"{}", None),
+                ];
+                outbox
+                    .store_events(&db, &events)
+                    .await
+                    .expect("Can't store events");
+
+                let publisher = InMemoryPublisher::new();
+                let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(outbox.clone());
+
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 2);
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+
+                let keys: Vec<_> = publisher
+                    .events()
+                    .iter()
+                    .map(|event| event.key.clone())
+                    .collect();
+                assert_eq!(
+                    keys,
+                    vec![Some("now".to_string()), Some("user".to_string())]
+                );
+                assert_eq!(count(&db, outbox.table(), "published_at IS NULL").await, 1);
+            }
+
+            #[async_std::test]
+            async fn discards_expired_events() {
+                let Some((db, outbox)) = setup("expiry").await else {
+                    return;
+                };
+                let now = Utc::now();
+                let events = vec![
+                    event::new(&"otp.sent", Some("stale"), &"123456", None)
+                        .with_expires_at(now - chrono::Duration::seconds(1)),
+                    event::new(&"otp.sent", Some("fresh"), &"654321", None)
+                        .with_expires_at(now + chrono::Duration::minutes(5)),
+                ];
+                outbox
+                    .store_events(&db, &events)
+                    .await
+                    .expect("Can't store events");
+
+                let publisher = InMemoryPublisher::new();
+                let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(outbox.clone());
+
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 2);
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+
+                let published = publisher.events();
+                assert_eq!(published.len(), 1);
+                assert_eq!(published[0].key.as_deref(), Some("fresh"));
+                assert!(published[0].headers.contains_key(event::EXPIRES_AT_HEADER));
+                assert_eq!(
+                    count(
+                        &db,
+                        outbox.table(),
+                        "expired_at IS NOT NULL AND published_at IS NULL"
+                    )
+                    .await,
+                    1
+                );
+            }
+
+            #[async_std::test]
+            async fn dead_letters_events_after_failed_attempts() {
+                let Some((db, outbox)) = setup("attempts").await else {
+                    return;
+                };
+                outbox
+                    .store_events(&db, &[user_banned(), user_created()])
+                    .await
+                    .expect("Can't store events");
+
+                let publisher = RejectingPublisher::default();
+                let relay = Relay::new(db.clone(), publisher.clone())
+                    .with_outbox(outbox.clone())
+                    .with_backoff(
+                        Backoff::new()
+                            .with_initial_delay(Duration::ZERO)
+                            .with_max_attempts(2),
+                    );
+
+                assert!(relay.relay_batch().await.is_err());
+                assert_eq!(
+                    count(
+                        &db,
+                        outbox.table(),
+                        "attempts = 1 AND last_error IS NOT NULL"
+                    )
+                    .await,
+                    1
+                );
+                assert!(relay.relay_batch().await.is_err());
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 1);
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 0);
+
+                assert_eq!(publisher.inner.events()[0].topic, "user.created");
+                assert_eq!(
+                    count(
+                        &db,
+                        outbox.table(),
+                        "dead_lettered_at IS NOT NULL AND published_at IS NULL"
+                    )
+                    .await,
+                    1
+                );
+
+                let dead_letters = outbox
+                    .dead_letters(&db, 10)
+                    .await
+                    .expect("Can't list dead letters");
+                assert_eq!(dead_letters.len(), 1);
+                assert_eq!(dead_letters[0].topic, "user.banned");
+                assert_eq!(
+                    dead_letters[0].error,
+                    "can't publish event: topic is forbidden"
+                );
+                assert_eq!(dead_letters[0].handler, None);
+            }
+
+            #[async_std::test]
+            async fn archives_published_and_dead_lettered_rows() {
+                let outbox =
+                    Outbox::new().with_header_column(event::TRACEPARENT_HEADER, "trace_context");
+                let Some((db, outbox)) = setup_with("retention", outbox).await else {
+                    return;
+                };
+                for table in [outbox.table().to_string(), format!("{}_archive", outbox.table())] {
+                    sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN trace_context TEXT"))
+                        .execute(&db)
+                        .await
+                        .expect("Can't add header column");
+                }
+
+                let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+                let mut event = Event {
+                    idempotency_key: Some("create-user".to_string()),
+                    ..user_created()
+                }
+                .with_event_type("UserCreated")
+                .expect("Can't set event type");
+                event
+                    .headers
+                    .insert(event::TRACEPARENT_HEADER.to_string(), traceparent.to_string());
+                outbox
+                    .store_events(&db, &[event, user_banned()])
+                    .await
+                    .expect("Can't store events");
+
+                let relay = Relay::new(db.clone(), RejectingPublisher::default())
+                    .with_outbox(outbox.clone())
+                    .with_backoff(Backoff::new().with_max_attempts(1));
+                assert!(relay.relay_batch().await.is_err());
+
+                // Timestamps of SQLite have a precision of seconds
+                async_std::task::sleep(Duration::from_secs(1)).await;
+                let retention = Retention::new(db.clone())
+                    .with_outbox(outbox.clone())
+                    .with_retention_period(Duration::ZERO)
+                    .with_action(RetentionAction::Archive);
+                assert_eq!(retention.purge().await.expect("Can't purge"), 2);
+
+                let archive = format!("{}_archive", outbox.table());
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 0);
+                assert_eq!(
+                    count(
+                        &db,
+                        &archive,
+                        &format!(
+                            "idempotency_key = 'create-user' AND event_type = 'UserCreated' AND trace_context = '{traceparent}'"
+                        )
+                    )
+                    .await,
+                    1
+                );
+                assert_eq!(
+                    count(&db, &archive, "dead_lettered_at IS NOT NULL AND attempts = 1").await,
+                    1
+                );
+                // Dead letters outlive rows of the outbox table
+                assert_eq!(
+                    outbox
+                        .dead_letters(&db, 10)
+                        .await
+                        .expect("Can't list dead letters")
+                        .len(),
+                    1
+                );
+            }
+
+            #[async_std::test]
+            async fn worker_skips_expired_events() {
+                let Some((db, outbox)) = setup("worker_expiry").await else {
+                    return;
+                };
+                let expired =
+                    user_created().with_expires_at(Utc::now() - chrono::Duration::seconds(1));
+                let unknown = event::new(&"user.unknown", Some("user"), &"{}", None);
+
+                let skip_reasons =
+                    run_worker(&db, &outbox, vec![expired, user_created(), unknown]).await;
+
+                assert_eq!(
+                    skip_reasons,
+                    vec![SkipReason::Expired, SkipReason::NoHandlers]
+                );
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 1);
+            }
+
+            #[async_std::test]
+            async fn stores_events_within_transaction() {
+                let Some((db, outbox)) = setup("transaction").await else {
+                    return;
+                };
+                let events: Vec<Event> = (0..3)
+                    .map(|key| event::new(&"panacea.test", Some(key), &"payload", None))
+                    .collect();
+
+                let mut tx = db.begin().await.expect("Can't begin transaction");
+                outbox
+                    .store_events(&mut tx, &events)
+                    .await
+                    .expect("Can't store events");
+                tx.rollback().await.expect("Can't rollback transaction");
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 0);
+
+                let mut tx = db.begin().await.expect("Can't begin transaction");
+                outbox
+                    .store_events(&mut tx, &events)
+                    .await
+                    .expect("Can't store events");
+                tx.commit().await.expect("Can't commit transaction");
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 3);
+            }
+
+            #[async_std::test]
+            async fn outbox_transaction_stores_events_on_commit() {
+                let Some((db, outbox)) = setup("outbox_transaction").await else {
+                    return;
+                };
+
+                let mut tx = outbox.begin(&db).await.expect("Can't begin transaction");
+                tx.emit(user_created());
+                tx.rollback().await.expect("Can't rollback transaction");
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 0);
+
+                let mut tx = outbox.begin(&db).await.expect("Can't begin transaction");
+                tx.emit(user_created());
+                tx.emit(user_banned());
+                tx.commit().await.expect("Can't commit transaction");
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 2);
+            }
+
+            #[async_std::test]
+            async fn deduplicates_events_by_idempotency_key() {
+                let Some((db, outbox)) = setup("idempotency").await else {
+                    return;
+                };
+
+                let id = outbox
+                    .store_event(&db, user_created().with_idempotency_key("signup-1"))
+                    .await
+                    .expect("Can't store event");
+                let duplicate = outbox
+                    .store_event(&db, user_created().with_idempotency_key("signup-1"))
+                    .await
+                    .expect("Can't store duplicate event");
+                assert_eq!(duplicate, id);
+
+                // A duplicate doesn't abort the transaction it's stored within
+                let mut tx = db.begin().await.expect("Can't begin transaction");
+                let duplicate = outbox
+                    .store_event(&mut tx, user_created().with_idempotency_key("signup-1"))
+                    .await
+                    .expect("Can't store duplicate event");
+                assert_eq!(duplicate, id);
+                outbox
+                    .store_events(
+                        &mut tx,
+                        &[
+                            user_banned().with_idempotency_key("signup-1"),
+                            user_banned().with_idempotency_key("ban-1"),
+                        ],
+                    )
+                    .await
+                    .expect("Can't store events");
+                tx.commit().await.expect("Can't commit transaction");
+
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 2);
+
+                // Only duplicate keys are skipped, not events with a duplicate id
+                let duplicate_id = Event { id, ..user_banned() }.with_idempotency_key("ban-2");
+                assert!(outbox.store_events(&db, &[duplicate_id]).await.is_err());
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 2);
+            }
+
+            #[async_std::test]
+            async fn streams_events_in_order() {
+                let Some((db, outbox)) = setup("stream").await else {
+                    return;
+                };
+                let events = vec![user_created(), user_banned(), user_created()];
+                outbox
+                    .store_events(&db, &events)
+                    .await
+                    .expect("Can't store events");
+
+                let streamed: Vec<Event> = outbox
+                    .stream(&db, 0)
+                    .try_collect()
+                    .await
+                    .expect("Can't stream events");
+                let ids: Vec<_> = streamed.iter().map(|event| event.id).collect();
+                assert_eq!(ids, events.iter().map(|event| event.id).collect::<Vec<_>>());
+
+                let sequence = streamed[1].sequence.expect("Event has no sequence");
+                let resumed: Vec<_> = outbox
+                    .stream(&db, sequence)
+                    .map(|event| event.expect("Can't stream event").id)
+                    .collect()
+                    .await;
+                assert_eq!(resumed, ids[1..]);
+            }
+
+            #[async_std::test]
+            async fn stores_events_in_debezium_layout() {
+                let outbox = Outbox::new()
+                    .with_columns(Columns::debezium())
+                    .with_header_column(event::TRACEPARENT_HEADER, "tracingspancontext");
+                let Some((db, outbox)) = setup_with("debezium", outbox).await else {
+                    return;
+                };
+                sqlx::query(&format!(
+                    "ALTER TABLE {} ADD COLUMN tracingspancontext TEXT",
+                    outbox.table()
+                ))
+                .execute(&db)
+                .await
+                .expect("Can't add header column");
+
+                let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+                let mut event = user_created()
+                    .with_event_type("UserCreated")
+                    .expect("Can't set event type");
+                event
+                    .headers
+                    .insert(event::TRACEPARENT_HEADER.to_string(), traceparent.to_string());
+                outbox
+                    .store_event(&db, event)
+                    .await
+                    .expect("Can't store event");
+                outbox
+                    .store_events(&db, &[user_banned()])
+                    .await
+                    .expect("Can't store events");
+
+                let rows: Vec<(String, String, Option<String>, Option<String>)> =
+                    sqlx::query_as(&format!(
+                        r#"SELECT aggregatetype, aggregateid, "type", tracingspancontext FROM {} ORDER BY sequence"#,
+                        outbox.table()
+                    ))
+                    .fetch_all(&db)
+                    .await
+                    .expect("Can't fetch rows");
+                assert_eq!(
+                    rows,
+                    [
+                        (
+                            "user.created".to_string(),
+                            "user".to_string(),
+                            Some("UserCreated".to_string()),
+                            Some(traceparent.to_string()),
+                        ),
+                        ("user.banned".to_string(), "user".to_string(), None, None),
+                    ]
+                );
+
+                // The relay reads the same rows back into events
+                let publisher = InMemoryPublisher::new();
+                let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(outbox.clone());
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 2);
+
+                let published = publisher.events();
+                assert_eq!(published[0].topic, "user.created");
+                assert_eq!(published[0].key.as_deref(), Some("user"));
+                assert_eq!(
+                    published[0].event_type().expect("Can't parse event type"),
+                    Some("UserCreated".to_string())
+                );
+                assert_eq!(published[1].topic, "user.banned");
+
+                // Requeued dead letters of purged rows get their event type and header columns
+                // back
+                let mut event = user_banned()
+                    .with_event_type("UserBanned")
+                    .expect("Can't set event type");
+                event
+                    .headers
+                    .insert(event::TRACEPARENT_HEADER.to_string(), traceparent.to_string());
+                outbox
+                    .store_event(&db, event.clone())
+                    .await
+                    .expect("Can't store event");
+                let relay = Relay::new(db.clone(), RejectingPublisher::default())
+                    .with_outbox(outbox.clone())
+                    .with_backoff(Backoff::new().with_max_attempts(1));
+                assert!(relay.relay_batch().await.is_err());
+                sqlx::query(&format!(
+                    "DELETE FROM {} WHERE dead_lettered_at IS NOT NULL",
+                    outbox.table()
+                ))
+                .execute(&db)
+                .await
+                .expect("Can't purge row");
+
+                let dead_letters = outbox
+                    .dead_letters(&db, 10)
+                    .await
+                    .expect("Can't list dead letters");
+                assert!(outbox
+                    .requeue_dead_letter(&db, dead_letters[0].sequence)
+                    .await
+                    .expect("Can't requeue dead letter"));
+
+                let row = outbox
+                    .find_row(&db, event.id)
+                    .await
+                    .expect("Can't find row")
+                    .expect("Event is not requeued");
+                let header_column: Option<String> = sqlx::query_scalar(&format!(
+                    "SELECT tracingspancontext FROM {} WHERE sequence = {}",
+                    outbox.table(),
+                    row.sequence
+                ))
+                .fetch_one(&db)
+                .await
+                .expect("Can't fetch header column");
+                assert_eq!(row.topic, "user.banned");
+                assert_eq!(row.event_type.as_deref(), Some("UserBanned"));
+                assert_eq!(header_column.as_deref(), Some(traceparent));
+            }
+
+            #[async_std::test]
+            async fn worker_stores_handled_events() {
+                let Some((db, outbox)) = setup("worker").await else {
+                    return;
+                };
+
+                let events = vec![user_created(), user_created()];
+                run_worker(&db, &outbox, events.clone()).await;
+
+                let publisher = InMemoryPublisher::new();
+                let relay = Relay::new(db.clone(), publisher.clone()).with_outbox(outbox.clone());
+
+                assert_eq!(relay.relay_batch().await.expect("Can't relay"), 2);
+                let published = publisher.events();
+                assert!(published.iter().all(|event| event.topic == "greeting.sent"));
+
+                // Handled events are linked to the events, that have caused them
+                let causes: Vec<_> = events.iter().map(|event| event.id.to_string()).collect();
+                for (event, cause) in published.iter().zip(&causes) {
+                    assert_eq!(event.headers[event::CAUSATION_ID_HEADER], *cause);
+                    assert_eq!(event.headers[event::CORRELATION_ID_HEADER], *cause);
+                }
+            }
+
+            #[async_std::test]
+            async fn worker_dead_letters_failed_events() {
+                let Some((db, outbox)) = setup("dead_letters").await else {
+                    return;
+                };
+                let event = Event {
+                    deliver_at: Some(Utc::now() - chrono::Duration::minutes(1)),
+                    expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+                    idempotency_key: Some("ban-user".to_string()),
+                    ..user_banned()
+                };
+
+                let skip_reasons =
+                    run_worker(&db, &outbox, vec![event.clone(), event.clone()]).await;
+
+                assert_eq!(skip_reasons, vec![SkipReason::DeadLettered]);
+                let dead_letters = outbox
+                    .dead_letters(&db, 10)
+                    .await
+                    .expect("Can't list dead letters");
+                assert_eq!(dead_letters.len(), 1);
+                assert_eq!(dead_letters[0].handler.as_deref(), Some("reject"));
+                assert_eq!(dead_letters[0].error, "user is banned");
+                assert_eq!(dead_letters[0].attempts, 2);
+
+                assert_eq!(dead_letters[0].origin, DeadLetterOrigin::Worker);
+
+                // The dead letter keeps the whole event
+                let dead_lettered = outbox
+                    .event_from_dead_letter(&dead_letters[0])
+                    .expect("Can't decode event");
+                assert_eq!(dead_lettered.id, event.id);
+                assert!(dead_lettered.deliver_at.is_some());
+                assert!(dead_lettered.expires_at.is_some());
+                assert_eq!(dead_lettered.idempotency_key.as_deref(), Some("ban-user"));
+
+                // Consumed events are not published to every consumer of the topic again
+                assert!(matches!(
+                    outbox
+                        .requeue_dead_letter(&db, dead_letters[0].sequence)
+                        .await,
+                    Err(panacea::outbox::Error::NotRequeueable(_))
+                ));
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 0);
+            }
+
+            #[async_std::test]
+            async fn worker_dead_letters_events_it_cant_open() {
+                let Some((db, outbox)) = setup("dead_letters_unopened").await else {
+                    return;
+                };
+                let mut event = user_created();
+                event.payload = b"ciphertext".to_vec();
+                event
+                    .headers
+                    .insert(event::ENCRYPTION_KEY_ID_HEADER.to_string(), "unknown".to_string());
+
+                let skip_reasons =
+                    run_worker(&db, &outbox, vec![event.clone(), event.clone()]).await;
+
+                assert_eq!(skip_reasons, vec![SkipReason::DeadLettered]);
+                let dead_letters = outbox
+                    .dead_letters(&db, 10)
+                    .await
+                    .expect("Can't list dead letters");
+                assert_eq!(dead_letters.len(), 1);
+                assert_eq!(dead_letters[0].handler, None);
+                assert_eq!(
+                    dead_letters[0].error,
+                    "can't decrypt payload: encryption is not configured"
+                );
+                assert_eq!(dead_letters[0].attempts, 2);
+
+                // The event is stored as is, so it can be opened once the key is configured
+                assert_eq!(dead_letters[0].payload, b"ciphertext");
+                assert!(dead_letters[0].headers.contains(event::ENCRYPTION_KEY_ID_HEADER));
+            }
+
+            #[async_std::test]
+            async fn worker_doesnt_dead_letter_events_without_id() {
+                let Some((db, outbox)) = setup("dead_letters_without_id").await else {
+                    return;
+                };
+                let events = (0..2)
+                    .map(|_| Event {
+                        id: Uuid::nil(),
+                        ..user_banned()
+                    })
+                    .collect();
+
+                let skip_reasons = run_worker(&db, &outbox, events).await;
+
+                // Unrelated events without id don't share a failure counter
+                assert!(skip_reasons.is_empty());
+                assert_eq!(count(&db, &format!("{}_dead_letters", outbox.table()), "1 = 1").await, 0);
+            }
+
+            #[async_std::test]
+            async fn worker_rolls_back_failed_handling() {
+                let Some((db, outbox)) = setup("rollback").await else {
+                    return;
+                };
+
+                run_worker(&db, &outbox, vec![user_banned(), user_created()]).await;
+
+                // Greeting of the banned user is rolled back along with the failed handling.
+                assert_eq!(count(&db, outbox.table(), "1 = 1").await, 1);
+            }
+        }
+    };
+}
+
+scenarios!(sqlite, SqlitePool, connect_sqlite);
+scenarios!(postgres, PgPool, connect_postgres);